@@ -1,7 +1,10 @@
 pub mod v1_puzrs;
 
+#[cfg(feature = "json")]
 static mut SHARED_ARRAY: Vec<u8> = vec![];
 
+const HOSTS: [&str; 3] = ["puzz.link", "pzv.jp", "pzprxs.vercel.app"];
+
 fn hex_to_i32(c: u8) -> Option<i32> {
     match c {
         b'0'..=b'9' => Some((c - b'0') as i32),
@@ -10,87 +13,235 @@ fn hex_to_i32(c: u8) -> Option<i32> {
     }
 }
 
-fn decode_url(url: &str) -> Option<(usize, usize, Vec<i32>)> {
+fn i32_to_hex(n: i32) -> char {
+    char::from_digit(n as u32, 16).unwrap()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DecodeError {
+    /// The URL does not point to one of the supported puzzle hosts.
+    UnknownHost,
+    /// The URL is not a numlin puzzle.
+    UnknownPuzzleType,
+    /// The number of `/`-separated parts after the puzzle type is not 3.
+    WrongPartCount(usize),
+    /// The width or height is not a positive integer.
+    InvalidSize,
+    /// The character at `offset` is not a valid hexadecimal digit.
+    BadHexDigit { offset: usize },
+    /// The `-` or `+` escape at `offset` is not followed by enough digits.
+    TruncatedEscape { offset: usize },
+    /// The data from `offset` on does not fit in the grid.
+    DataOverflow { offset: usize },
+    /// The data describes only `decoded` of the `expected` cells.
+    TooLittleData { decoded: usize, expected: usize },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecodeError::UnknownHost => write!(f, "unknown host"),
+            DecodeError::UnknownPuzzleType => write!(f, "unknown puzzle type"),
+            DecodeError::WrongPartCount(n) => write!(f, "expected 3 parts, found {}", n),
+            DecodeError::InvalidSize => write!(f, "invalid width or height"),
+            DecodeError::BadHexDigit { offset } => {
+                write!(f, "bad hexadecimal digit at offset {}", offset)
+            }
+            DecodeError::TruncatedEscape { offset } => {
+                write!(f, "truncated number at offset {}", offset)
+            }
+            DecodeError::DataOverflow { offset } => {
+                write!(f, "data overflows the grid at offset {}", offset)
+            }
+            DecodeError::TooLittleData { decoded, expected } => {
+                write!(f, "data covers only {} of {} cells", decoded, expected)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl DecodeError {
+    fn to_json(self) -> String {
+        let (kind, extra) = match self {
+            DecodeError::UnknownHost => ("unknownHost", String::new()),
+            DecodeError::UnknownPuzzleType => ("unknownPuzzleType", String::new()),
+            DecodeError::WrongPartCount(n) => ("wrongPartCount", format!(",\"parts\":{}", n)),
+            DecodeError::InvalidSize => ("invalidSize", String::new()),
+            DecodeError::BadHexDigit { offset } => {
+                ("badHexDigit", format!(",\"offset\":{}", offset))
+            }
+            DecodeError::TruncatedEscape { offset } => {
+                ("truncatedEscape", format!(",\"offset\":{}", offset))
+            }
+            DecodeError::DataOverflow { offset } => {
+                ("dataOverflow", format!(",\"offset\":{}", offset))
+            }
+            DecodeError::TooLittleData { decoded, expected } => (
+                "tooLittleData",
+                format!(",\"decoded\":{},\"expected\":{}", decoded, expected),
+            ),
+        };
+        format!(
+            "{{\"status\":\"error\",\"description\":\"failed to decode URL: {}\",\"error\":{{\"kind\":\"{}\"{}}}}}",
+            self, kind, extra
+        )
+    }
+}
+
+/// Decode a numlin URL into `(height, width, clues)`.
+/// Offsets in the returned errors are byte offsets into `url`.
+pub fn decode_url(url: &str) -> Result<(usize, usize, Vec<i32>), DecodeError> {
+    let url_len = url.len();
     let url = url
         .strip_prefix("http://")
-        .or_else(|| url.strip_prefix("https://"))?;
-    let url = url
-        .strip_prefix("puzz.link/")
-        .or_else(|| url.strip_prefix("pzv.jp/"))
-        .or_else(|| url.strip_prefix("pzprxs.vercel.app/"))?;
+        .or_else(|| url.strip_prefix("https://"))
+        .ok_or(DecodeError::UnknownHost)?;
+    let url = HOSTS
+        .iter()
+        .find_map(|host| url.strip_prefix(host).and_then(|url| url.strip_prefix('/')))
+        .ok_or(DecodeError::UnknownHost)?;
     let url = url
         .strip_prefix("p?")
-        .or_else(|| url.strip_prefix("p.html?"))?;
+        .or_else(|| url.strip_prefix("p.html?"))
+        .ok_or(DecodeError::UnknownPuzzleType)?;
     let url = url
         .strip_prefix("numlin/")
-        .or_else(|| url.strip_prefix("numberlink/"))?;
+        .or_else(|| url.strip_prefix("numberlink/"))
+        .ok_or(DecodeError::UnknownPuzzleType)?;
 
     let parts = url.split('/').collect::<Vec<_>>();
     if parts.len() != 3 {
-        return None;
+        return Err(DecodeError::WrongPartCount(parts.len()));
     }
 
-    let width = parts[0].parse::<usize>().ok()?;
-    let height = parts[1].parse::<usize>().ok()?;
+    let width = parts[0]
+        .parse::<usize>()
+        .map_err(|_| DecodeError::InvalidSize)?;
+    let height = parts[1]
+        .parse::<usize>()
+        .map_err(|_| DecodeError::InvalidSize)?;
+    if width == 0 || height == 0 {
+        return Err(DecodeError::InvalidSize);
+    }
     let puzzle_data = parts[2].as_bytes();
+    let data_offset = url_len - puzzle_data.len();
+
+    let hex_at = |i: usize| {
+        hex_to_i32(puzzle_data[i]).ok_or(DecodeError::BadHexDigit {
+            offset: data_offset + i,
+        })
+    };
 
     let mut ret = vec![0; height * width];
     let mut idx = 0;
     let mut i = 0;
 
     while i < puzzle_data.len() {
+        let start = i;
         if b'g' <= puzzle_data[i] && puzzle_data[i] <= b'z' {
             idx += (puzzle_data[i] - b'f') as usize;
             i += 1;
-            continue;
         } else {
             let n;
             if puzzle_data[i] == b'-' {
                 if i + 2 >= puzzle_data.len() {
-                    return None;
+                    return Err(DecodeError::TruncatedEscape {
+                        offset: data_offset + i,
+                    });
                 }
-                let high = hex_to_i32(puzzle_data[i + 1])?;
-                let low = hex_to_i32(puzzle_data[i + 2])?;
+                let high = hex_at(i + 1)?;
+                let low = hex_at(i + 2)?;
                 n = high * 16 + low;
                 i += 3;
             } else if puzzle_data[i] == b'+' {
                 if i + 3 >= puzzle_data.len() {
-                    return None;
+                    return Err(DecodeError::TruncatedEscape {
+                        offset: data_offset + i,
+                    });
                 }
-                let high = hex_to_i32(puzzle_data[i + 1])?;
-                let mid = hex_to_i32(puzzle_data[i + 2])?;
-                let low = hex_to_i32(puzzle_data[i + 3])?;
+                let high = hex_at(i + 1)?;
+                let mid = hex_at(i + 2)?;
+                let low = hex_at(i + 3)?;
                 n = high * 256 + mid * 16 + low;
                 i += 4;
             } else {
-                n = hex_to_i32(puzzle_data[i])?;
+                n = hex_at(i)?;
                 i += 1;
             }
-            if idx >= ret.len() {
-                return None;
+            if idx < ret.len() {
+                ret[idx] = n;
             }
-            ret[idx] = n;
             idx += 1;
         }
+        if idx > ret.len() {
+            return Err(DecodeError::DataOverflow {
+                offset: data_offset + start,
+            });
+        }
+    }
+    if idx < ret.len() {
+        return Err(DecodeError::TooLittleData {
+            decoded: idx,
+            expected: ret.len(),
+        });
     }
 
-    Some((height, width, ret))
+    Ok((height, width, ret))
 }
 
-#[unsafe(no_mangle)]
-fn enumerate_answers_problem(url: *const u8, len: usize, limit: usize) -> *const u8 {
-    let url = unsafe { std::slice::from_raw_parts(url, len) };
-    let url = std::str::from_utf8(url).unwrap_or("");
-    let problem = decode_url(url);
-
-    let ret_string;
-    if problem.is_none() {
-        ret_string = "{\"status\":\"error\",\"description\":\"failed to decode URL\"}".to_string();
-    } else {
-        let (height, width, problem) = problem.unwrap();
-        ret_string = v1_puzrs::solve_problem(&problem, height as i32, width as i32, limit);
+/// Encode a numlin problem into a URL for `host` (one of `puzz.link`, `pzv.jp` and
+/// `pzprxs.vercel.app`), using the shortest encoding `decode_url` accepts.
+/// Returns `None` if `clues` contains a number which cannot be encoded (negative or
+/// larger than 4095) or `host` is not supported.
+pub fn encode_url(height: usize, width: usize, clues: &[i32], host: &str) -> Option<String> {
+    if clues.len() != height * width || !HOSTS.contains(&host) {
+        return None;
     }
 
+    let mut data = String::new();
+    let mut n_skip = 0;
+    for &n in clues {
+        if n == 0 {
+            n_skip += 1;
+            if n_skip == 20 {
+                data.push('z');
+                n_skip = 0;
+            }
+            continue;
+        }
+        if n_skip > 0 {
+            data.push((b'f' + n_skip) as char);
+            n_skip = 0;
+        }
+        match n {
+            1..=15 => data.push(i32_to_hex(n)),
+            16..=255 => {
+                data.push('-');
+                data.push(i32_to_hex(n >> 4));
+                data.push(i32_to_hex(n & 15));
+            }
+            256..=4095 => {
+                data.push('+');
+                data.push(i32_to_hex(n >> 8));
+                data.push(i32_to_hex((n >> 4) & 15));
+                data.push(i32_to_hex(n & 15));
+            }
+            _ => return None,
+        }
+    }
+    if n_skip > 0 {
+        data.push((b'f' + n_skip) as char);
+    }
+
+    Some(format!(
+        "https://{}/p?numlin/{}/{}/{}",
+        host, width, height, data
+    ))
+}
+
+#[cfg(feature = "json")]
+fn to_shared_array(ret_string: String) -> *const u8 {
     let ret_len = ret_string.len();
 
     #[allow(static_mut_refs)]
@@ -105,3 +256,114 @@ fn enumerate_answers_problem(url: *const u8, len: usize, limit: usize) -> *const
         SHARED_ARRAY.as_ptr()
     }
 }
+
+#[cfg(feature = "json")]
+#[unsafe(no_mangle)]
+fn enumerate_answers_problem(url: *const u8, len: usize, limit: usize) -> *const u8 {
+    let url = unsafe { std::slice::from_raw_parts(url, len) };
+    let url = std::str::from_utf8(url).unwrap_or("");
+    let ret_string = match decode_url(url) {
+        Ok((height, width, problem)) => {
+            v1_puzrs::solve_problem(&problem, height as i32, width as i32, limit)
+        }
+        Err(err) => err.to_json(),
+    };
+    to_shared_array(ret_string)
+}
+
+#[cfg(feature = "json")]
+#[unsafe(no_mangle)]
+fn check_uniqueness_problem(url: *const u8, len: usize) -> *const u8 {
+    let url = unsafe { std::slice::from_raw_parts(url, len) };
+    let url = std::str::from_utf8(url).unwrap_or("");
+    let ret_string = match decode_url(url) {
+        Ok((height, width, problem)) => {
+            v1_puzrs::check_problem_uniqueness(&problem, height as i32, width as i32)
+        }
+        Err(err) => err.to_json(),
+    };
+    to_shared_array(ret_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_url() {
+        let clues = vec![1, 0, 0, 0, 2, 0, 1, 0, 0, 2, 0, 0];
+        assert_eq!(
+            encode_url(3, 4, &clues, "puzz.link"),
+            Some("https://puzz.link/p?numlin/4/3/1i2g1h2h".to_string())
+        );
+        assert_eq!(encode_url(3, 4, &clues, "example.com"), None);
+        assert_eq!(encode_url(2, 4, &clues, "puzz.link"), None);
+
+        let mut clues = vec![0; 30];
+        clues[0] = -1;
+        assert_eq!(encode_url(5, 6, &clues, "puzz.link"), None);
+        clues[0] = 4096;
+        assert_eq!(encode_url(5, 6, &clues, "puzz.link"), None);
+    }
+
+    #[test]
+    fn test_url_round_trip() {
+        let mut clues = vec![0; 9 * 7];
+        clues[0] = 15;
+        clues[1] = 16;
+        clues[2] = 255;
+        clues[3] = 256;
+        clues[30] = 4095;
+        clues[52] = 1000;
+        clues[53] = 7;
+        for host in HOSTS {
+            let url = encode_url(9, 7, &clues, host).unwrap();
+            assert_eq!(decode_url(&url), Ok((9, 7, clues.clone())));
+        }
+
+        let url = encode_url(9, 7, &clues, "puzz.link").unwrap();
+        assert_eq!(
+            url,
+            "https://puzz.link/p?numlin/7/9/f-10-ff+100zl+fffzg+3e87o"
+        );
+    }
+
+    #[test]
+    fn test_decode_url_errors() {
+        assert_eq!(
+            decode_url("https://example.com/p?numlin/2/2/1h1"),
+            Err(DecodeError::UnknownHost)
+        );
+        assert_eq!(
+            decode_url("https://puzz.link/p?nurikabe/2/2/1h1"),
+            Err(DecodeError::UnknownPuzzleType)
+        );
+        assert_eq!(
+            decode_url("https://puzz.link/p?numlin/2/2"),
+            Err(DecodeError::WrongPartCount(2))
+        );
+        assert_eq!(
+            decode_url("https://puzz.link/p?numlin/2/0/"),
+            Err(DecodeError::InvalidSize)
+        );
+        assert_eq!(
+            decode_url("https://puzz.link/p?numlin/2/2/1gA1"),
+            Err(DecodeError::BadHexDigit { offset: 33 })
+        );
+        assert_eq!(
+            decode_url("https://puzz.link/p?numlin/2/2/1g1-0"),
+            Err(DecodeError::TruncatedEscape { offset: 34 })
+        );
+        assert_eq!(
+            decode_url("https://puzz.link/p?numlin/2/2/1h11"),
+            Err(DecodeError::DataOverflow { offset: 34 })
+        );
+        assert_eq!(
+            decode_url("https://puzz.link/p?numlin/2/2/1g1"),
+            Err(DecodeError::TooLittleData {
+                decoded: 3,
+                expected: 4
+            })
+        );
+    }
+}
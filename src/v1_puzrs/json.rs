@@ -0,0 +1,218 @@
+use super::P;
+use super::*;
+
+fn outcome_to_str(outcome: SolveOutcome) -> &'static str {
+    match outcome {
+        SolveOutcome::Exhausted => "exhausted",
+        SolveOutcome::AnswerLimit => "answerLimit",
+        SolveOutcome::Budget => "budget",
+        SolveOutcome::NotFullyFilled => "notFullyFilled",
+    }
+}
+
+fn answer_common(problem: &[i32], height: i32, width: i32) -> String {
+    let mut toks = vec![];
+    for y in 0..height {
+        for x in 0..width {
+            let n = problem[(y * width + x) as usize];
+            if 1 <= n {
+                toks.push(format!("{{\"y\":{},\"x\":{},\"color\":\"black\",\"item\":{{\"kind\":\"text\",\"data\":\"{}\"}}}}", y * 2 + 1, x * 2 + 1, n));
+            }
+        }
+    }
+    format!(
+        "{{\"kind\":\"grid\",\"height\":{},\"width\":{},\"defaultStyle\":\"grid\",\"data\":[{}]}}",
+        height,
+        width,
+        &toks.join(",")
+    )
+}
+
+fn push_line_tokens(
+    toks: &mut Vec<String>,
+    ans: &LinePlacement,
+    height: i32,
+    width: i32,
+    color: &str,
+    item: &str,
+) {
+    for y in 0..height {
+        for x in 0..width {
+            if x < width - 1 && ans.right(P(y, x)) {
+                toks.push(format!(
+                    "{{\"y\":{},\"x\":{},\"color\":\"{}\",\"item\":\"{}\"}}",
+                    y * 2 + 1,
+                    x * 2 + 2,
+                    color,
+                    item
+                ));
+            }
+            if y < height - 1 && ans.down(P(y, x)) {
+                toks.push(format!(
+                    "{{\"y\":{},\"x\":{},\"color\":\"{}\",\"item\":\"{}\"}}",
+                    y * 2 + 2,
+                    x * 2 + 1,
+                    color,
+                    item
+                ));
+            }
+        }
+    }
+}
+
+fn board_json(toks: &[String], height: i32, width: i32) -> String {
+    format!(
+        "{{\"kind\":\"grid\",\"height\":{},\"width\":{},\"defaultStyle\":\"empty\",\"data\":[{}]}}",
+        height,
+        width,
+        &toks.join(",")
+    )
+}
+
+fn answer_to_json(ans: &AnswerReport, height: i32, width: i32) -> String {
+    let mut toks = vec![];
+    push_line_tokens(
+        &mut toks,
+        ans.solution.line_placement(),
+        height,
+        width,
+        "green",
+        "line",
+    );
+
+    if let Some(extra_path) = &ans.extra_path {
+        push_line_tokens(
+            &mut toks,
+            extra_path.line_placement(),
+            height,
+            width,
+            "red",
+            "dottedLine",
+        );
+    }
+
+    board_json(&toks, height, width)
+}
+
+fn problem_error_to_json(err: ProblemError) -> String {
+    let description = match err {
+        ProblemError::InvalidSize { height, width } => {
+            format!("invalid size {}x{}", height, width)
+        }
+        ProblemError::ClueCountMismatch { expected, actual } => {
+            format!("expected {} clues, found {}", expected, actual)
+        }
+    };
+    format!(
+        "{{\"status\":\"error\",\"description\":\"{}\"}}",
+        description
+    )
+}
+
+fn diagnostics_to_json(diagnostics: &[ProblemDiagnostic]) -> String {
+    let cell_json = |(y, x): (i32, i32)| format!("{{\"y\":{},\"x\":{}}}", y, x);
+    let toks = diagnostics
+        .iter()
+        .map(|diagnostic| match diagnostic {
+            ProblemDiagnostic::InvalidClue { clue, cell } => format!(
+                "{{\"kind\":\"invalidClue\",\"clue\":{},\"cells\":[{}]}}",
+                clue,
+                cell_json(*cell)
+            ),
+            ProblemDiagnostic::ClueCount { clue, cells } => format!(
+                "{{\"kind\":\"clueCount\",\"clue\":{},\"cells\":[{}]}}",
+                clue,
+                cells
+                    .iter()
+                    .map(|&c| cell_json(c))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            ProblemDiagnostic::IsolatedClue { clue, cell } => format!(
+                "{{\"kind\":\"isolatedClue\",\"clue\":{},\"cells\":[{}]}}",
+                clue,
+                cell_json(*cell)
+            ),
+            ProblemDiagnostic::UnreachablePair { clue, cells } => format!(
+                "{{\"kind\":\"unreachablePair\",\"clue\":{},\"cells\":[{},{}]}}",
+                clue,
+                cell_json(cells[0]),
+                cell_json(cells[1])
+            ),
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "{{\"status\":\"error\",\"description\":\"invalid problem\",\"diagnostics\":[{}]}}",
+        toks.join(",")
+    )
+}
+
+pub fn solve_problem(problem: &[i32], height: i32, width: i32, limit: usize) -> String {
+    let board = match Problem::from_clues(height, width, problem) {
+        Ok(board) => board,
+        Err(err) => return problem_error_to_json(err),
+    };
+    let diagnostics = board.validate();
+    if !diagnostics.is_empty() {
+        return diagnostics_to_json(&diagnostics);
+    }
+    let options = SolveOptions {
+        limit: Some(limit),
+        ..SolveOptions::default()
+    };
+    let result = solve(&board, &options);
+    if result.answers.is_empty() {
+        return format!(
+            "{{\"status\":\"error\",\"description\":\"no answer\",\"outcome\":\"{}\"}}",
+            outcome_to_str(result.outcome)
+        );
+    }
+
+    let common_json = answer_common(problem, height, width);
+    let boards = result
+        .answers
+        .iter()
+        .map(|ans| answer_to_json(ans, height, width))
+        .collect::<Vec<_>>();
+    format!(
+        "{{\"status\":\"ok\",\"description\":{{\"common\":{},\"answers\":[{}],\"isUnique\":{},\"outcome\":\"{}\"}}}}",
+        common_json,
+        boards.join(","),
+        result.is_unique,
+        outcome_to_str(result.outcome)
+    )
+}
+
+/// Like `solve_problem`, but reports an exact uniqueness verdict (see `check_uniqueness`)
+/// instead of enumerating canonical answers.
+pub fn check_problem_uniqueness(problem: &[i32], height: i32, width: i32) -> String {
+    let board = match Problem::from_clues(height, width, problem) {
+        Ok(board) => board,
+        Err(err) => return problem_error_to_json(err),
+    };
+    let diagnostics = board.validate();
+    if !diagnostics.is_empty() {
+        return diagnostics_to_json(&diagnostics);
+    }
+    let (verdict, answers) = match check_uniqueness(&board, false) {
+        Uniqueness::Unique(ans) => ("unique", vec![ans]),
+        Uniqueness::Multiple(ans1, ans2) => ("multiple", vec![ans1, ans2]),
+        Uniqueness::NoSolution => ("noSolution", vec![]),
+    };
+
+    let common_json = answer_common(problem, height, width);
+    let boards = answers
+        .iter()
+        .map(|ans| {
+            let mut toks = vec![];
+            push_line_tokens(&mut toks, ans, height, width, "green", "line");
+            board_json(&toks, height, width)
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "{{\"status\":\"ok\",\"description\":{{\"common\":{},\"answers\":[{}],\"uniqueness\":\"{}\"}}}}",
+        common_json,
+        boards.join(","),
+        verdict
+    )
+}
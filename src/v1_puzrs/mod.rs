@@ -1,11 +1,22 @@
 use std::ops::Index;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Instant;
 
+#[cfg(feature = "json")]
+mod json;
+mod problem;
 mod solver2;
 mod util;
+mod validate;
 
+#[cfg(feature = "json")]
+pub use self::json::*;
+pub use self::problem::*;
 pub use self::solver2::*;
+pub use self::validate::*;
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Clue(pub i32);
 
 pub const NO_CLUE: Clue = Clue(0);
@@ -13,7 +24,7 @@ pub const UNUSED: Clue = Clue(-1);
 
 use util::{D, FOUR_NEIGHBOURS, Grid, LP, P};
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct LinePlacement {
     right: Grid<bool>,
     down: Grid<bool>,
@@ -125,9 +136,137 @@ impl LinePlacement {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct SolveOptions {
+    pub limit: Option<usize>,
+    pub disallow_unused_cell: bool,
+    pub terminate_on_not_fully_filled: bool,
+    /// Enumerate only answers in canonical form (no U-turns next to clue-free diagonals,
+    /// L-chains yielded to the left), which is much faster; turn it off to get every answer
+    /// satisfying the plain rules. If unused cells are allowed, any answer can be shortened
+    /// into a canonical one, so this still tells whether a puzzle has an answer. With
+    /// `disallow_unused_cell` it does not: a puzzle may have answers none of which is
+    /// canonical (e.g. `[1, 1 / 0, 0 / 0, 0]`), and then no answer is found.
+    pub canonical: bool,
+    /// Stop the search once this many search steps are taken.
+    pub max_steps: Option<u64>,
+    /// Stop the search once this point in time is reached.
+    pub deadline: Option<Instant>,
+    /// Stop the search once this flag is set, typically from another thread.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Probe the undecided edges ahead of the search (see `Probing`).
+    pub probing: Option<Probing>,
+    pub branching: Branching,
+    /// Extra deduction rules, or constraints of a variant of the puzzle (see `Propagator`).
+    /// As with `probing`, other canonical answers may be found.
+    pub propagators: Vec<Arc<dyn Propagator>>,
+}
+impl Default for SolveOptions {
+    fn default() -> SolveOptions {
+        SolveOptions {
+            limit: None,
+            disallow_unused_cell: false,
+            terminate_on_not_fully_filled: false,
+            canonical: true,
+            max_steps: None,
+            deadline: None,
+            cancel: None,
+            probing: None,
+            branching: Branching::RowMajor,
+            propagators: vec![],
+        }
+    }
+}
+
+/// Failed-literal probing between the levels of the search.
+///
+/// Each undecided edge of the cells from the one being searched to the end of the row
+/// `depth` rows below is tentatively decided to be a line and then a blank. If one of
+/// them leads to a contradiction, the edge is decided the other way, and this is repeated
+/// until no more edges are decided. The search takes fewer steps at the cost of more work
+/// per step (the probes are not counted as steps). The answers are the same unless
+/// `canonical` is set, in which case other canonical answers may be found.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Probing {
+    pub depth: i32,
+    /// Probe at every `interval`-th level of the search tree. Must be positive.
+    pub interval: usize,
+}
+
+/// What the search branches on at each level.
+#[derive(Clone, Debug)]
+pub enum Branching {
+    /// The right and down edges of each cell in row-major order.
+    RowMajor,
+    /// The undecided edges around the cell with the fewest ways to go on: the end of a
+    /// chain, which is extended through one of them, or (if every cell must be used) an
+    /// unused cell with three undecided edges, one of which is left blank.
+    /// This often takes fewer steps on large boards, but the answers are found in another
+    /// order, and the canonical-form rules specific to the row-major order are not
+    /// applied, so `canonical` yields more answers.
+    MostConstrained,
+    /// The order given by a `BranchingStrategy`. As with `MostConstrained`, the
+    /// canonical-form rules specific to the row-major order are not applied.
+    /// Checkpoints do not tell strategies apart, so they must be resumed with the same one.
+    Custom(Arc<dyn BranchingStrategy>),
+}
+
+impl PartialEq for Branching {
+    fn eq(&self, other: &Branching) -> bool {
+        match (self, other) {
+            (Branching::Custom(a), Branching::Custom(b)) => Arc::ptr_eq(a, b),
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+impl Eq for Branching {}
+
+/// Verdict of `check_uniqueness`.
+pub enum Uniqueness {
+    Unique(LinePlacement),
+    Multiple(LinePlacement, LinePlacement),
+    NoSolution,
+}
+
+/// Why the search in `solve2` stopped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SolveOutcome {
+    /// The whole search space was explored, so `answers` holds every answer (possibly none).
+    Exhausted,
+    /// The search stopped as soon as `limit` answers were found.
+    AnswerLimit,
+    /// The search was stopped by `max_steps`, `deadline` or `cancel` before it was exhausted.
+    Budget,
+    /// The search stopped at an answer leaving some cells unused
+    /// (see `terminate_on_not_fully_filled`).
+    NotFullyFilled,
+}
+/// Why `SolutionIter::resume` rejected a checkpoint.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CheckpointError {
+    /// The data is not a checkpoint written by `SolutionIter::checkpoint`.
+    Malformed,
+    /// The checkpoint was written for another problem or with other options.
+    ProblemMismatch,
+    /// The saved branches do not lead to a state of the search.
+    InvalidState,
+}
+
+/// State of a running search, passed to the callback of `solve2_with_progress`.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub n_steps: u64,
+    /// The cell being branched on.
+    pub y: i32,
+    pub x: i32,
+    /// The number of answers found so far.
+    pub n_answers: usize,
+    /// A rough estimate of the explored fraction of the search tree, between 0 and 1.
+    pub explored: f64,
+}
 pub struct AnswerDetail {
     pub answers: Vec<LinePlacement>,
-    pub fully_checked: bool,
+    pub outcome: SolveOutcome,
     pub found_not_fully_filled: bool,
     pub n_steps: u64,
 }
@@ -135,6 +274,9 @@ impl AnswerDetail {
     pub fn len(&self) -> usize {
         self.answers.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.answers.is_empty()
+    }
 }
 impl Index<usize> for AnswerDetail {
     type Output = LinePlacement;
@@ -142,26 +284,11 @@ impl Index<usize> for AnswerDetail {
         &self.answers[idx]
     }
 }
-
-fn answer_common(problem: &[i32], height: i32, width: i32) -> String {
-    let mut toks = vec![];
-    for y in 0..height {
-        for x in 0..width {
-            let n = problem[(y * width + x) as usize];
-            if 1 <= n {
-                toks.push(format!("{{\"y\":{},\"x\":{},\"color\":\"black\",\"item\":{{\"kind\":\"text\",\"data\":\"{}\"}}}}", y * 2 + 1, x * 2 + 1, n));
-            }
-        }
-    }
-    format!(
-        "{{\"kind\":\"grid\",\"height\":{},\"width\":{},\"defaultStyle\":\"grid\",\"data\":[{}]}}",
-        height,
-        width,
-        &toks.join(",")
-    )
-}
-
-fn find_extra_answer(ans: &LinePlacement, height: i32, width: i32) -> Option<LinePlacement> {
+pub(crate) fn find_extra_answer(
+    ans: &LinePlacement,
+    height: i32,
+    width: i32,
+) -> Option<LinePlacement> {
     let mut unit_id = vec![vec![-1; width as usize]; height as usize];
     let mut next_id = 0;
     for y in 0..height {
@@ -274,90 +401,3 @@ fn find_extra_answer(ans: &LinePlacement, height: i32, width: i32) -> Option<Lin
 
     None
 }
-
-fn answer_to_json(ans: &LinePlacement, height: i32, width: i32) -> (String, bool) {
-    let mut toks = vec![];
-    for y in 0..height {
-        for x in 0..width {
-            if x < width - 1 && ans.right(P(y, x)) {
-                toks.push(format!(
-                    "{{\"y\":{},\"x\":{},\"color\":\"green\",\"item\":\"line\"}}",
-                    y * 2 + 1,
-                    x * 2 + 2
-                ));
-            }
-            if y < height - 1 && ans.down(P(y, x)) {
-                toks.push(format!(
-                    "{{\"y\":{},\"x\":{},\"color\":\"green\",\"item\":\"line\"}}",
-                    y * 2 + 2,
-                    x * 2 + 1
-                ));
-            }
-        }
-    }
-
-    let extra_path = find_extra_answer(ans, height, width);
-    if let Some(extra_path) = &extra_path {
-        for y in 0..height {
-            for x in 0..width {
-                if x < width - 1 && extra_path.right(P(y, x)) {
-                    toks.push(format!(
-                        "{{\"y\":{},\"x\":{},\"color\":\"red\",\"item\":\"dottedLine\"}}",
-                        y * 2 + 1,
-                        x * 2 + 2
-                    ));
-                }
-                if y < height - 1 && extra_path.down(P(y, x)) {
-                    toks.push(format!(
-                        "{{\"y\":{},\"x\":{},\"color\":\"red\",\"item\":\"dottedLine\"}}",
-                        y * 2 + 2,
-                        x * 2 + 1
-                    ));
-                }
-            }
-        }
-    }
-
-    let res = format!(
-        "{{\"kind\":\"grid\",\"height\":{},\"width\":{},\"defaultStyle\":\"empty\",\"data\":[{}]}}",
-        height,
-        width,
-        &toks.join(",")
-    );
-
-    (res, extra_path.is_some())
-}
-
-pub fn solve_problem(problem: &[i32], height: i32, width: i32, limit: usize) -> String {
-    let mut board = Grid::new(height, width, NO_CLUE);
-    for y in 0..height {
-        for x in 0..width {
-            board[P(y, x)] = Clue(problem[(y * width + x) as usize]);
-        }
-    }
-    let res = solve2(&board, Some(limit), false, false).answers;
-    let ret_string;
-    if res.len() == 0 {
-        ret_string = "{\"status\":\"error\",\"description\":\"no answer\"}".to_owned();
-    } else {
-        let common_json = answer_common(problem, height, width);
-
-        let mut boards = vec![];
-        let mut is_unique = res.len() == 1;
-        for r in &res {
-            let (b, has_extra) = answer_to_json(r, height, width);
-            boards.push(b);
-
-            if has_extra {
-                is_unique = false;
-            }
-        }
-
-        let ans_json = boards.join(",");
-        ret_string = format!(
-            "{{\"status\":\"ok\",\"description\":{{\"common\":{},\"answers\":[{}],\"isUnique\":{}}}}}",
-            common_json, ans_json, is_unique
-        );
-    }
-    ret_string
-}
@@ -0,0 +1,211 @@
+use super::*;
+use super::{Grid, P};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProblemError {
+    /// The height or the width is not positive.
+    InvalidSize { height: i32, width: i32 },
+    /// The number of clues is not `height * width`.
+    ClueCountMismatch { expected: usize, actual: usize },
+}
+
+/// A Numberlink problem: a grid of clues (`NO_CLUE`, `UNUSED` or a positive number).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Problem {
+    clues: Grid<Clue>,
+}
+
+impl Problem {
+    /// Create a problem of the given size without any clues.
+    pub fn new(height: i32, width: i32) -> Result<Problem, ProblemError> {
+        if height <= 0 || width <= 0 {
+            return Err(ProblemError::InvalidSize { height, width });
+        }
+        Ok(Problem {
+            clues: Grid::new(height, width, NO_CLUE),
+        })
+    }
+    pub(crate) fn from_grid(clues: Grid<Clue>) -> Problem {
+        Problem { clues }
+    }
+    /// Create a problem from clues in row-major order.
+    pub fn from_clues(height: i32, width: i32, clues: &[i32]) -> Result<Problem, ProblemError> {
+        let mut ret = Problem::new(height, width)?;
+        let expected = (height * width) as usize;
+        if clues.len() != expected {
+            return Err(ProblemError::ClueCountMismatch {
+                expected,
+                actual: clues.len(),
+            });
+        }
+        for (i, &c) in clues.iter().enumerate() {
+            ret.clues[i] = Clue(c);
+        }
+        Ok(ret)
+    }
+    pub fn height(&self) -> i32 {
+        self.clues.height()
+    }
+    pub fn width(&self) -> i32 {
+        self.clues.width()
+    }
+    pub fn is_valid_cell(&self, y: i32, x: i32) -> bool {
+        self.clues.is_valid_p(P(y, x))
+    }
+    /// Clue at cell `(y, x)`, which must be inside the grid.
+    pub fn clue(&self, y: i32, x: i32) -> Clue {
+        assert!(self.is_valid_cell(y, x));
+        self.clues[P(y, x)]
+    }
+    pub fn set_clue(&mut self, y: i32, x: i32, clue: Clue) {
+        assert!(self.is_valid_cell(y, x));
+        self.clues[P(y, x)] = clue;
+    }
+    /// Clues in row-major order, in the format accepted by `from_clues`.
+    pub fn to_clues(&self) -> Vec<i32> {
+        (0..(self.height() * self.width()) as usize)
+            .map(|i| self.clues[i].0)
+            .collect()
+    }
+    pub fn validate(&self) -> Vec<ProblemDiagnostic> {
+        validate_problem(self)
+    }
+    /// The clue grid as taken by `solve2`.
+    pub(crate) fn grid(&self) -> &Grid<Clue> {
+        &self.clues
+    }
+    pub fn solve(&self, options: &SolveOptions) -> SolveResult {
+        solve(self, options)
+    }
+    /// Answers of the problem, found lazily as the iterator is advanced.
+    pub fn solutions(&self, options: &SolveOptions) -> SolutionIter<'static> {
+        SolutionIter::new(self, options)
+    }
+}
+
+/// An answer of a `Problem`: which pairs of adjacent cells are connected by a line.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Solution {
+    placement: LinePlacement,
+}
+
+impl Solution {
+    pub fn height(&self) -> i32 {
+        self.placement.height()
+    }
+    pub fn width(&self) -> i32 {
+        self.placement.width()
+    }
+    /// Whether cells `(y, x)` and `(y, x + 1)` are connected.
+    pub fn right(&self, y: i32, x: i32) -> bool {
+        self.placement.right(P(y, x))
+    }
+    /// Whether cells `(y, x)` and `(y + 1, x)` are connected.
+    pub fn down(&self, y: i32, x: i32) -> bool {
+        self.placement.down(P(y, x))
+    }
+    /// Whether no line passes through cell `(y, x)`.
+    pub fn is_unused(&self, y: i32, x: i32) -> bool {
+        self.placement.isolated(P(y, x))
+    }
+    pub fn line_placement(&self) -> &LinePlacement {
+        &self.placement
+    }
+}
+
+impl From<LinePlacement> for Solution {
+    fn from(placement: LinePlacement) -> Solution {
+        Solution { placement }
+    }
+}
+
+impl From<Solution> for LinePlacement {
+    fn from(solution: Solution) -> LinePlacement {
+        solution.placement
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AnswerReport {
+    pub solution: Solution,
+    /// A path which connects two cells of the same chain through unused cells, if any.
+    /// Its existence shows that the problem has another answer.
+    pub extra_path: Option<Solution>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SolveResult {
+    pub answers: Vec<AnswerReport>,
+    /// Whether exactly one answer was found and it has no `extra_path`.
+    /// This is what the board viewer reports; use `check_uniqueness` for a proof.
+    pub is_unique: bool,
+    pub outcome: SolveOutcome,
+    pub n_steps: u64,
+}
+
+/// Solve `problem` and report the answers together with the uniqueness verdict.
+pub fn solve(problem: &Problem, options: &SolveOptions) -> SolveResult {
+    let height = problem.height();
+    let width = problem.width();
+    let detail = solve2_with_options(problem, options);
+
+    let answers = detail
+        .answers
+        .into_iter()
+        .map(|ans| {
+            let extra_path = find_extra_answer(&ans, height, width).map(Solution::from);
+            AnswerReport {
+                solution: Solution::from(ans),
+                extra_path,
+            }
+        })
+        .collect::<Vec<_>>();
+    let is_unique = answers.len() == 1 && answers[0].extra_path.is_none();
+
+    SolveResult {
+        answers,
+        is_unique,
+        outcome: detail.outcome,
+        n_steps: detail.n_steps,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_problem() {
+        assert_eq!(
+            Problem::new(0, 3),
+            Err(ProblemError::InvalidSize {
+                height: 0,
+                width: 3
+            })
+        );
+        assert_eq!(
+            Problem::from_clues(2, 3, &[1, 0, 1]),
+            Err(ProblemError::ClueCountMismatch {
+                expected: 6,
+                actual: 3
+            })
+        );
+
+        let mut problem = Problem::from_clues(2, 3, &[1, 0, 1, 0, 0, 0]).unwrap();
+        assert!(problem.clue(0, 0) == Clue(1));
+        problem.set_clue(1, 2, UNUSED);
+        assert_eq!(problem.to_clues(), vec![1, 0, 1, 0, 0, -1]);
+
+        let result = problem.solve(&SolveOptions::default());
+        assert_eq!(result.outcome, SolveOutcome::Exhausted);
+        assert_eq!(result.answers.len(), 1);
+        let solution = &result.answers[0].solution;
+        assert!(solution.right(0, 0) && solution.right(0, 1));
+        assert!(solution.is_unused(1, 0));
+
+        // the line could take a detour through (1, 0) and (1, 1)
+        assert!(!result.is_unique);
+        let extra_path = result.answers[0].extra_path.as_ref().unwrap();
+        assert!(extra_path.down(0, 0) && extra_path.right(1, 0));
+    }
+}
@@ -1,23 +1,49 @@
 use super::*;
 use super::{D, Grid, LP, P};
 use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
+mod extension;
+mod parallel;
+mod portfolio;
+
+pub use self::extension::*;
+pub use self::parallel::*;
+pub use self::portfolio::*;
+
+/// The state of an edge between two adjacent cells.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
-enum Edge {
+pub enum Edge {
     Undecided,
     Line,
     Blank,
 }
 
+#[derive(Clone)]
 enum History {
     AnotherEnd(i32, i32),
     Edge(LP),
     Inconsistent(bool),
-    OpenEndCount(i32, i32),
+    OpenEndCount(usize, i32, i32),
     NumberEnd(i32, (i32, i32)),
     Checkpoint,
 }
 
+/// A pending step of `SolverField::propagate`.
+#[derive(Clone)]
+enum Task {
+    Decide(LP, Edge),
+    /// Decide the edge unless it has been decided in the meantime.
+    DecideUndecided(LP, Edge),
+    /// Apply the canonical-form rules around a line edge (the second half if `true`).
+    Canonical(LP, bool),
+    Inspect(P),
+    AdjacentEnd(P, D, i32),
+}
+
+#[derive(Clone)]
 struct SolverField {
     another_end: Grid<i32>,        // height * width
     has_clue: Grid<bool>,          // height * width
@@ -28,121 +54,194 @@ struct SolverField {
     edge: Grid<Edge>,              // (2 * height - 1) * (2 * width - 1)
     inconsistent: bool,
     disallow_unused_cell: bool,
+    canonical: bool,
     history: Vec<History>,
+    tasks: Vec<Task>,
 
-    // for cut-based pruning
-    undecided_count: Vec<i32>,   // width - 1
-    open_end_count: Vec<i32>,    // width
-    number_end: Vec<(i32, i32)>, // max clue
+    // for cut-based pruning; index 0 is for the cuts between columns, and 1 for those
+    // between rows
+    undecided_count: [Vec<i32>; 2], // width - 1, height - 1
+    open_end_count: [Vec<i32>; 2],  // width, height
+    number_end: Vec<(i32, i32)>,    // max clue (cell ids)
+
+    scratch: Scratch,
+}
+
+/// Buffers of the checks run during the search, kept so that the checks do not allocate.
+/// Their contents mean nothing between the checks.
+#[derive(Clone, Default)]
+struct Scratch {
+    // for `prune_straight_cut`
+    accsum: Vec<i32>,
+    // for `prune_unreachable`
+    group: Vec<usize>,
+    // for `prune_dead_region` and `prune_dead_region_near`
+    areas: Areas,
+    // for `SolverField::inspect_bottlenecks`
+    dfs: Dfs,
+    required_by: Vec<Option<usize>>,
+    bottlenecks: Vec<(usize, usize)>,
 }
 
 const CLOSED_END: i32 = -1;
+/// The largest area checked by `prune_dead_region_near`.
+const DEAD_REGION_MAX_CELLS: usize = 32;
 
 impl SolverField {
-    fn new(problem: &Grid<Clue>, disallow_unused_cell: bool) -> SolverField {
+    fn new(problem: &Grid<Clue>, disallow_unused_cell: bool, canonical: bool) -> SolverField {
+        let mut ret = SolverField {
+            another_end: Grid::new(0, 0, 0),
+            has_clue: Grid::new(0, 0, false),
+            unused: Grid::new(0, 0, false),
+            down_left: Grid::new(0, 0, false),
+            down_right: Grid::new(0, 0, false),
+            left_clue_distance: Grid::new(0, 0, 0),
+            edge: Grid::new(0, 0, Edge::Undecided),
+            inconsistent: false,
+            disallow_unused_cell,
+            canonical,
+            history: Vec::new(),
+            tasks: Vec::new(),
+            undecided_count: [Vec::new(), Vec::new()],
+            open_end_count: [Vec::new(), Vec::new()],
+            number_end: Vec::new(),
+            scratch: Scratch::default(),
+        };
+        ret.reset(problem, disallow_unused_cell, canonical);
+        ret
+    }
+    /// Set up the field for `problem`, which may be of another size, reusing the buffers.
+    fn reset(&mut self, problem: &Grid<Clue>, disallow_unused_cell: bool, canonical: bool) {
         let height = problem.height();
         let width = problem.width();
-        let mut another_end = Grid::new(height, width, 0);
-        let mut edge = Grid::new(height * 2 - 1, width * 2 - 1, Edge::Undecided);
-        let mut has_clue = Grid::new(height, width, false);
-        let mut unused = Grid::new(height, width, false);
+        self.another_end.reset(height, width, 0);
+        self.edge
+            .reset(height * 2 - 1, width * 2 - 1, Edge::Undecided);
+        self.has_clue.reset(height, width, false);
+        self.unused.reset(height, width, false);
         let mut max_clue = 0;
         for y in 0..height {
             for x in 0..width {
                 let pos = P(y, x);
                 let c = problem[pos];
                 if c == UNUSED {
-                    has_clue[pos] = true;
-                    unused[pos] = true;
+                    self.has_clue[pos] = true;
+                    self.unused[pos] = true;
 
                     if y > 0 {
-                        edge[LP::of_vertex(pos) + D(-1, 0)] = Edge::Blank;
+                        self.edge[LP::of_vertex(pos) + D(-1, 0)] = Edge::Blank;
                     }
                     if x > 0 {
-                        edge[LP::of_vertex(pos) + D(0, -1)] = Edge::Blank;
+                        self.edge[LP::of_vertex(pos) + D(0, -1)] = Edge::Blank;
                     }
                     if y < height - 1 {
-                        edge[LP::of_vertex(pos) + D(1, 0)] = Edge::Blank;
+                        self.edge[LP::of_vertex(pos) + D(1, 0)] = Edge::Blank;
                     }
                     if x < width - 1 {
-                        edge[LP::of_vertex(pos) + D(0, 1)] = Edge::Blank;
+                        self.edge[LP::of_vertex(pos) + D(0, 1)] = Edge::Blank;
                     }
                 } else if c == NO_CLUE {
-                    let id = another_end.index_p(pos) as i32;
-                    another_end[pos] = id;
+                    let id = self.another_end.index_p(pos) as i32;
+                    self.another_end[pos] = id;
                 } else {
                     max_clue = ::std::cmp::max(max_clue, c.0);
-                    another_end[pos] = -(c.0 + 1);
-                    has_clue[pos] = true;
+                    self.another_end[pos] = -(c.0 + 1);
+                    self.has_clue[pos] = true;
                 }
             }
         }
-        let mut down_left = Grid::new(height, width, false);
-        let mut down_right = Grid::new(height, width, false);
+        self.down_left.reset(height, width, false);
+        self.down_right.reset(height, width, false);
         for y in 0..height {
             let y = height - 1 - y;
             for x in 0..width {
                 if y != height - 1 {
                     let pos = P(y, x);
-                    if x > 0 && (down_left[pos + D(1, -1)] || has_clue[pos + D(1, -1)]) {
-                        down_left[pos] = true;
+                    if x > 0 && (self.down_left[pos + D(1, -1)] || self.has_clue[pos + D(1, -1)]) {
+                        self.down_left[pos] = true;
                     }
-                    if x < width - 1 && (down_right[pos + D(1, 1)] || has_clue[pos + D(1, 1)]) {
-                        down_right[pos] = true;
+                    if x < width - 1
+                        && (self.down_right[pos + D(1, 1)] || self.has_clue[pos + D(1, 1)])
+                    {
+                        self.down_right[pos] = true;
                     }
                 }
             }
         }
-        let mut left_clue_distance = Grid::new(height, width, 0);
+        self.left_clue_distance.reset(height, width, 0);
         for y in 0..height {
             let mut d = width;
             for x in 0..width {
                 d += 1;
                 let pos = P(y, x);
-                left_clue_distance[pos] = d;
-                if has_clue[pos] {
+                self.left_clue_distance[pos] = d;
+                if self.has_clue[pos] {
                     d = 0;
                 }
             }
         }
-        let undecided_count = vec![height; (width - 1) as usize];
-        let open_end_count = vec![0; width as usize];
-        let mut number_end = vec![(-1, -1); (max_clue + 1) as usize];
+        // edges around `UNUSED` cells are already blank
+        for (axis, len) in [(0, width - 1), (1, height - 1)] {
+            self.undecided_count[axis].clear();
+            self.undecided_count[axis].resize(len as usize, 0);
+        }
+        for y in 0..(height * 2 - 1) {
+            for x in 0..(width * 2 - 1) {
+                if self.edge[LP(y, x)] != Edge::Undecided {
+                    continue;
+                }
+                if y % 2 == 0 && x % 2 == 1 {
+                    self.undecided_count[0][(x / 2) as usize] += 1;
+                } else if y % 2 == 1 && x % 2 == 0 {
+                    self.undecided_count[1][(y / 2) as usize] += 1;
+                }
+            }
+        }
+        for (axis, len) in [(0, width), (1, height)] {
+            self.open_end_count[axis].clear();
+            self.open_end_count[axis].resize(len as usize, 0);
+        }
+        self.number_end.clear();
+        self.number_end.resize((max_clue + 1) as usize, (-1, -1));
         for y in 0..height {
             for x in 0..width {
                 let Clue(c) = problem[P(y, x)];
                 if c > 0 {
                     let c = c as usize;
-                    if number_end[c].0 == -1 {
-                        number_end[c].0 = x;
+                    let id = problem.index_p(P(y, x)) as i32;
+                    if self.number_end[c].0 == -1 {
+                        self.number_end[c].0 = id;
                     } else {
-                        number_end[c].1 = x;
+                        self.number_end[c].1 = id;
                     }
                 }
             }
         }
-        let mut ret = SolverField {
-            another_end,
-            has_clue,
-            unused,
-            down_left,
-            down_right,
-            left_clue_distance,
-            edge,
-            inconsistent: false,
-            disallow_unused_cell,
-            history: Vec::new(),
-            undecided_count,
-            open_end_count,
-            number_end,
-        };
+        let size = (height * width) as usize;
+        self.scratch.areas.reset(height, width);
+        self.scratch.dfs.order.resize(size, 0);
+        self.scratch.dfs.low.resize(size, 0);
+        self.scratch.dfs.parent.resize(size, usize::MAX);
+        self.scratch.required_by.resize(size, None);
+        self.inconsistent = false;
+        self.disallow_unused_cell = disallow_unused_cell;
+        self.canonical = canonical;
+        self.history.clear();
+        self.tasks.clear();
         if disallow_unused_cell {
-            for y in 0..height {
-                for x in 0..width {
-                    ret.inspect(P(y, x));
+            for y in (0..height).rev() {
+                for x in (0..width).rev() {
+                    self.tasks.push(Task::Inspect(P(y, x)));
                 }
             }
+            self.propagate();
         }
+    }
+    /// Run `check` with the scratch buffers of the field.
+    fn with_scratch<R>(&mut self, check: impl FnOnce(&SolverField, &mut Scratch) -> R) -> R {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        let ret = check(self, &mut scratch);
+        self.scratch = scratch;
         ret
     }
     fn get_edge(&self, pos: LP) -> Edge {
@@ -179,24 +278,24 @@ impl SolverField {
     fn set_inconsistent(&mut self) -> bool {
         self.history.push(History::Inconsistent(self.inconsistent));
         self.inconsistent = true;
-        return true;
+        true
     }
     fn update_another_end(&mut self, id: i32, value: i32) {
         self.history
             .push(History::AnotherEnd(id, self.another_end[id as usize]));
         self.another_end[id as usize] = value;
     }
-    fn update_open_end_count(&mut self, x1: i32, x2: i32, sgn: i32) {
-        if x1 < x2 {
-            self.open_end_count[x1 as usize] += sgn;
-            self.open_end_count[x2 as usize] -= sgn;
-            self.history.push(History::OpenEndCount(x1, -sgn));
-            self.history.push(History::OpenEndCount(x2, sgn));
-        } else if x1 > x2 {
-            self.open_end_count[x2 as usize] += sgn;
-            self.open_end_count[x1 as usize] -= sgn;
-            self.history.push(History::OpenEndCount(x2, -sgn));
-            self.history.push(History::OpenEndCount(x1, sgn));
+    fn update_open_end_count(&mut self, id1: i32, id2: i32, sgn: i32) {
+        let p1 = self.another_end.p(id1 as usize);
+        let p2 = self.another_end.p(id2 as usize);
+        for (axis, c1, c2) in [(0, p1.x(), p2.x()), (1, p1.y(), p2.y())] {
+            let (c1, c2) = (c1.min(c2), c1.max(c2));
+            if c1 < c2 {
+                self.open_end_count[axis][c1 as usize] += sgn;
+                self.open_end_count[axis][c2 as usize] -= sgn;
+                self.history.push(History::OpenEndCount(axis, c1, -sgn));
+                self.history.push(History::OpenEndCount(axis, c2, sgn));
+            }
         }
     }
     fn update_number_end(&mut self, n: i32, before: i32, after: i32) {
@@ -225,13 +324,15 @@ impl SolverField {
                 History::AnotherEnd(id, val) => self.another_end[id as usize] = val,
                 History::Edge(cd) => {
                     self.edge[cd] = Edge::Undecided;
-                    let LP(_, x) = cd;
+                    let LP(y, x) = cd;
                     if x % 2 == 1 {
-                        self.undecided_count[(x / 2) as usize] += 1;
+                        self.undecided_count[0][(x / 2) as usize] += 1;
+                    } else {
+                        self.undecided_count[1][(y / 2) as usize] += 1;
                     }
                 }
                 History::Inconsistent(ic) => self.inconsistent = ic,
-                History::OpenEndCount(x, app) => self.open_end_count[x as usize] += app,
+                History::OpenEndCount(axis, c, app) => self.open_end_count[axis][c as usize] += app,
                 History::NumberEnd(n, v) => self.number_end[n as usize] = v,
                 History::Checkpoint => break,
             }
@@ -285,9 +386,8 @@ impl SolverField {
                     }
                 }
                 (false, true) => {
-                    let ae1_x = self.another_end.p(another_end1_id as usize).x();
-                    self.update_open_end_count(ae1_x, end1.1, -1);
-                    self.update_number_end(-another_end2_id - 1, end2.1, ae1_x);
+                    self.update_open_end_count(another_end1_id, end1_id, -1);
+                    self.update_number_end(-another_end2_id - 1, end2_id, another_end1_id);
                     if end1_id != another_end1_id {
                         self.update_another_end(end1_id, CLOSED_END);
                     }
@@ -295,9 +395,8 @@ impl SolverField {
                     self.update_another_end(end2_id, CLOSED_END);
                 }
                 (true, false) => {
-                    let ae2_x = self.another_end.p(another_end2_id as usize).x();
-                    self.update_open_end_count(ae2_x, end2.1, -1);
-                    self.update_number_end(-another_end1_id - 1, end1.1, ae2_x);
+                    self.update_open_end_count(another_end2_id, end2_id, -1);
+                    self.update_number_end(-another_end1_id - 1, end1_id, another_end2_id);
                     if end2_id != another_end2_id {
                         self.update_another_end(end2_id, CLOSED_END);
                     }
@@ -305,11 +404,9 @@ impl SolverField {
                     self.update_another_end(end1_id, CLOSED_END);
                 }
                 (false, false) => {
-                    let ae1_x = self.another_end.p(another_end1_id as usize).x();
-                    let ae2_x = self.another_end.p(another_end2_id as usize).x();
-                    self.update_open_end_count(ae1_x, end1.1, -1);
-                    self.update_open_end_count(ae2_x, end2.1, -1);
-                    self.update_open_end_count(ae1_x, ae2_x, 1);
+                    self.update_open_end_count(another_end1_id, end1_id, -1);
+                    self.update_open_end_count(another_end2_id, end2_id, -1);
+                    self.update_open_end_count(another_end1_id, another_end2_id, 1);
                     if end1_id != another_end1_id {
                         self.update_another_end(end1_id, CLOSED_END);
                     }
@@ -326,174 +423,171 @@ impl SolverField {
         self.history.push(History::Edge(pos));
         self.edge[pos] = state;
         if x % 2 == 1 {
-            self.undecided_count[(x / 2) as usize] -= 1;
+            self.undecided_count[0][(x / 2) as usize] -= 1;
+        } else {
+            self.undecided_count[1][(y / 2) as usize] -= 1;
         }
 
-        // ensure canonical form
-        if state == Edge::Line {
-            if y % 2 == 0 {
-                if !self.down_right[P(y / 2, x / 2)] && self.get_edge(pos + D(1, -1)) == Edge::Line
-                {
-                    return self.set_inconsistent();
-                }
-                if !self.down_left[P(y / 2, x / 2 + 1)]
-                    && self.get_edge(pos + D(1, 1)) == Edge::Line
-                {
-                    return self.set_inconsistent();
-                }
-
-                if self.get_edge(pos + D(-2, 0)) == Edge::Line {
-                    if self.decide_edge(pos + D(-1, -1), Edge::Blank) {
-                        return true;
-                    }
-                    if self.decide_edge(pos + D(-1, 1), Edge::Blank) {
-                        return true;
-                    }
-                } else if self.get_edge(pos + D(-1, -1)) == Edge::Line {
-                    if self.decide_edge(pos + D(-2, 0), Edge::Blank) {
-                        return true;
-                    }
-                    if self.decide_edge(pos + D(-1, 1), Edge::Blank) {
-                        return true;
-                    }
-                } else if self.get_edge(pos + D(-1, 1)) == Edge::Line {
-                    if self.decide_edge(pos + D(-2, 0), Edge::Blank) {
-                        return true;
-                    }
-                    if self.decide_edge(pos + D(-1, -1), Edge::Blank) {
-                        return true;
-                    }
-                }
-
-                if self.get_edge(pos + D(2, 0)) == Edge::Line {
-                    if self.decide_edge(pos + D(1, -1), Edge::Blank) {
-                        return true;
-                    }
-                    if self.decide_edge(pos + D(1, 1), Edge::Blank) {
-                        return true;
-                    }
-                } else if self.get_edge(pos + D(1, -1)) == Edge::Line {
-                    if self.decide_edge(pos + D(2, 0), Edge::Blank) {
-                        return true;
-                    }
-                    if self.decide_edge(pos + D(1, 1), Edge::Blank) {
-                        return true;
-                    }
-
-                    // yielding L-chain
-                    if !self.has_clue[P(y / 2 + 1, x / 2 + 1)] {
-                        if self.decide_edge(pos + D(2, 2), Edge::Line) {
-                            return true;
-                        }
-                        if self.decide_edge(pos + D(3, 1), Edge::Line) {
-                            return true;
-                        }
-                    }
-                } else if self.get_edge(pos + D(1, 1)) == Edge::Line {
-                    if self.decide_edge(pos + D(2, 0), Edge::Blank) {
-                        return true;
-                    }
-                    if self.decide_edge(pos + D(1, -1), Edge::Blank) {
-                        return true;
-                    }
+        // the consequences are derived later by `propagate`, in the same order as
+        // a recursive propagation would derive them
+        self.tasks.push(Task::Inspect(end2));
+        self.tasks.push(Task::Inspect(end1));
+        if state == Edge::Line && self.canonical {
+            self.tasks.push(Task::Canonical(pos, false));
+        }
 
-                    // yielding L-chain
-                    if !self.has_clue[P(y / 2 + 1, x / 2)] {
-                        if self.decide_edge(pos + D(2, -2), Edge::Line) {
-                            return true;
+        false
+    }
+    /// Decide edge `pos` and propagate its consequences.
+    /// Returns `true` if the field became inconsistent.
+    fn assume(&mut self, pos: LP, state: Edge) -> bool {
+        if self.decide_edge(pos, state) {
+            self.tasks.clear();
+            return true;
+        }
+        self.propagate()
+    }
+    /// Failed-literal probing on the undecided edges of the cells from `(y, x)` to the end
+    /// of row `y + depth` (see `Probing`).
+    /// Returns `true` if the field became inconsistent.
+    fn probe(&mut self, y: i32, x: i32, depth: i32) -> bool {
+        let y_end = (y + depth).min(self.height() - 1);
+        // the canonical-form rules depend on the order in which edges are decided, so
+        // the probes rely only on the plain rules, which hold in every answer
+        let canonical = self.canonical;
+        self.canonical = false;
+        let mut inconsistent = false;
+        let mut updated = true;
+        'probe: while updated {
+            updated = false;
+            for py in y..=y_end {
+                for px in (if py == y { x } else { 0 })..self.width() {
+                    for pos in [LP(py * 2, px * 2 + 1), LP(py * 2 + 1, px * 2)] {
+                        if self.get_edge(pos) != Edge::Undecided {
+                            continue;
                         }
-                        if self.decide_edge(pos + D(3, -1), Edge::Line) {
-                            return true;
+                        for (state, other) in [(Edge::Line, Edge::Blank), (Edge::Blank, Edge::Line)]
+                        {
+                            self.add_checkpoint();
+                            let failed = self.assume(pos, state);
+                            self.rollback();
+                            if failed {
+                                self.canonical = canonical;
+                                inconsistent = self.assume(pos, other);
+                                self.canonical = false;
+                                if inconsistent {
+                                    break 'probe;
+                                }
+                                updated = true;
+                                break;
+                            }
                         }
                     }
                 }
-            } else {
-                if !self.down_left[P(y / 2, x / 2)] && self.get_edge(pos + D(-1, -1)) == Edge::Line
-                {
+            }
+        }
+        self.canonical = canonical;
+        inconsistent
+    }
+    /// Run pending tasks until no task is left or an inconsistency is found.
+    fn propagate(&mut self) -> bool {
+        while let Some(task) = self.tasks.pop() {
+            let inconsistent = match task {
+                Task::Decide(pos, state) => self.decide_edge(pos, state),
+                Task::DecideUndecided(pos, state) => {
+                    self.get_edge(pos) == Edge::Undecided && self.decide_edge(pos, state)
+                }
+                Task::Canonical(pos, second_half) => self.ensure_canonical(pos, second_half),
+                Task::Inspect(pos) => self.inspect(pos),
+                Task::AdjacentEnd(pos, d, another_end) => {
+                    self.inspect_adjacent_end(pos, d, another_end)
+                }
+            };
+            if inconsistent {
+                self.tasks.clear();
+                return true;
+            }
+        }
+        false
+    }
+    /// Queue decisions so that they are made in the order of `decisions`.
+    fn push_decisions(&mut self, decisions: &[(LP, Edge)]) {
+        for &(pos, state) in decisions.iter().rev() {
+            self.tasks.push(Task::Decide(pos, state));
+        }
+    }
+    /// Ensure canonical form around edge `pos`, which has been decided to be a line.
+    /// The rules on the upper (or left) side of `pos` are applied first, and those on
+    /// the other side once the consequences of the former are propagated.
+    fn ensure_canonical(&mut self, pos: LP, second_half: bool) -> bool {
+        use Edge::{Blank, Line};
+
+        let LP(y, x) = pos;
+        if y % 2 == 0 {
+            if !second_half {
+                if !self.down_right[P(y / 2, x / 2)] && self.get_edge(pos + D(1, -1)) == Line {
                     return self.set_inconsistent();
                 }
-                if !self.down_right[P(y / 2, x / 2)] && self.get_edge(pos + D(-1, 1)) == Edge::Line
-                {
+                if !self.down_left[P(y / 2, x / 2 + 1)] && self.get_edge(pos + D(1, 1)) == Line {
                     return self.set_inconsistent();
                 }
 
-                if self.get_edge(pos + D(0, -2)) == Edge::Line {
-                    if self.decide_edge(pos + D(-1, -1), Edge::Blank) {
-                        return true;
-                    }
-                    if self.decide_edge(pos + D(1, -1), Edge::Blank) {
-                        return true;
-                    }
-                } else if self.get_edge(pos + D(-1, -1)) == Edge::Line {
-                    if self.decide_edge(pos + D(0, -2), Edge::Blank) {
-                        return true;
-                    }
-                    if self.decide_edge(pos + D(1, -1), Edge::Blank) {
-                        return true;
-                    }
-
-                    // yielding L-chain
-                    if !self.has_clue[P(y / 2 + 1, x / 2 - 1)] {
-                        if self.decide_edge(pos + D(1, -3), Edge::Line) {
-                            return true;
-                        }
-                        if self.decide_edge(pos + D(2, -2), Edge::Line) {
-                            return true;
-                        }
-                    }
-                } else if self.get_edge(pos + D(1, -1)) == Edge::Line {
-                    if self.decide_edge(pos + D(0, -2), Edge::Blank) {
-                        return true;
-                    }
-                    if self.decide_edge(pos + D(-1, -1), Edge::Blank) {
-                        return true;
-                    }
+                self.tasks.push(Task::Canonical(pos, true));
+                if self.get_edge(pos + D(-2, 0)) == Line {
+                    self.push_decisions(&[(pos + D(-1, -1), Blank), (pos + D(-1, 1), Blank)]);
+                } else if self.get_edge(pos + D(-1, -1)) == Line {
+                    self.push_decisions(&[(pos + D(-2, 0), Blank), (pos + D(-1, 1), Blank)]);
+                } else if self.get_edge(pos + D(-1, 1)) == Line {
+                    self.push_decisions(&[(pos + D(-2, 0), Blank), (pos + D(-1, -1), Blank)]);
                 }
-
-                if self.get_edge(pos + D(0, 2)) == Edge::Line {
-                    if self.decide_edge(pos + D(-1, 1), Edge::Blank) {
-                        return true;
-                    }
-                    if self.decide_edge(pos + D(1, 1), Edge::Blank) {
-                        return true;
-                    }
-                } else if self.get_edge(pos + D(-1, 1)) == Edge::Line {
-                    if self.decide_edge(pos + D(0, 2), Edge::Blank) {
-                        return true;
-                    }
-                    if self.decide_edge(pos + D(1, 1), Edge::Blank) {
-                        return true;
-                    }
-
-                    // yielding L-chain
-                    if !self.has_clue[P(y / 2 + 1, x / 2 + 1)] {
-                        if self.decide_edge(pos + D(1, 3), Edge::Line) {
-                            return true;
-                        }
-                        if self.decide_edge(pos + D(2, 2), Edge::Line) {
-                            return true;
-                        }
-                    }
-                } else if self.get_edge(pos + D(1, 1)) == Edge::Line {
-                    if self.decide_edge(pos + D(0, 2), Edge::Blank) {
-                        return true;
-                    }
-                    if self.decide_edge(pos + D(-1, 1), Edge::Blank) {
-                        return true;
-                    }
+            } else if self.get_edge(pos + D(2, 0)) == Line {
+                self.push_decisions(&[(pos + D(1, -1), Blank), (pos + D(1, 1), Blank)]);
+            } else if self.get_edge(pos + D(1, -1)) == Line {
+                // yielding L-chain
+                if !self.has_clue[P(y / 2 + 1, x / 2 + 1)] {
+                    self.push_decisions(&[(pos + D(2, 2), Line), (pos + D(3, 1), Line)]);
                 }
+                self.push_decisions(&[(pos + D(2, 0), Blank), (pos + D(1, 1), Blank)]);
+            } else if self.get_edge(pos + D(1, 1)) == Line {
+                // yielding L-chain
+                if !self.has_clue[P(y / 2 + 1, x / 2)] {
+                    self.push_decisions(&[(pos + D(2, -2), Line), (pos + D(3, -1), Line)]);
+                }
+                self.push_decisions(&[(pos + D(2, 0), Blank), (pos + D(1, -1), Blank)]);
+            }
+        } else if !second_half {
+            if !self.down_left[P(y / 2, x / 2)] && self.get_edge(pos + D(-1, -1)) == Line {
+                return self.set_inconsistent();
+            }
+            if !self.down_right[P(y / 2, x / 2)] && self.get_edge(pos + D(-1, 1)) == Line {
+                return self.set_inconsistent();
             }
-        }
 
-        // check incident vertices
-        if self.inspect(end1) {
-            return true;
-        }
-        if self.inspect(end2) {
-            return true;
+            self.tasks.push(Task::Canonical(pos, true));
+            if self.get_edge(pos + D(0, -2)) == Line {
+                self.push_decisions(&[(pos + D(-1, -1), Blank), (pos + D(1, -1), Blank)]);
+            } else if self.get_edge(pos + D(-1, -1)) == Line {
+                // yielding L-chain
+                if !self.has_clue[P(y / 2 + 1, x / 2 - 1)] {
+                    self.push_decisions(&[(pos + D(1, -3), Line), (pos + D(2, -2), Line)]);
+                }
+                self.push_decisions(&[(pos + D(0, -2), Blank), (pos + D(1, -1), Blank)]);
+            } else if self.get_edge(pos + D(1, -1)) == Line {
+                self.push_decisions(&[(pos + D(0, -2), Blank), (pos + D(-1, -1), Blank)]);
+            }
+        } else if self.get_edge(pos + D(0, 2)) == Line {
+            self.push_decisions(&[(pos + D(-1, 1), Blank), (pos + D(1, 1), Blank)]);
+        } else if self.get_edge(pos + D(-1, 1)) == Line {
+            // yielding L-chain
+            if !self.has_clue[P(y / 2 + 1, x / 2 + 1)] {
+                self.push_decisions(&[(pos + D(1, 3), Line), (pos + D(2, 2), Line)]);
+            }
+            self.push_decisions(&[(pos + D(0, 2), Blank), (pos + D(1, 1), Blank)]);
+        } else if self.get_edge(pos + D(1, 1)) == Line {
+            self.push_decisions(&[(pos + D(0, 2), Blank), (pos + D(-1, 1), Blank)]);
         }
 
-        return false;
+        false
     }
 
     /// Inspect vertex `cd`.
@@ -515,64 +609,48 @@ impl SolverField {
             }
         }
 
-        let another_end = self.another_end[pos];
-        if another_end < -1 {
-            for &d in &FOUR_NEIGHBOURS {
-                let pos2 = pos + d;
-                if self.another_end.is_valid_p(pos2) {
-                    let another_end2 = self.another_end[pos2];
-                    if another_end2 < -1 {
-                        if self.decide_edge(
-                            LP::of_vertex(pos) + d,
-                            if another_end == another_end2 {
-                                Edge::Line
-                            } else {
-                                Edge::Blank
-                            },
-                        ) {
-                            return true;
-                        }
-                    }
-                }
-            }
-        }
-
-        if n_line >= 3 {
+        // Tasks are run in the reverse order of pushing: the degree rules below are
+        // applied only after the rule on adjacent ends. A contradiction is reported
+        // right away, as the order does not matter then.
+        let fill = if n_line >= 3 {
             return self.set_inconsistent();
-        }
-        if n_line == 2 {
-            for &d in &FOUR_NEIGHBOURS {
-                let pos2 = LP::of_vertex(pos) + d;
-                if self.get_edge(pos2) == Edge::Undecided {
-                    if self.decide_edge(pos2, Edge::Blank) {
-                        return true;
-                    }
-                }
-            }
+        } else if n_line == 2 {
+            Some(Edge::Blank)
         } else if n_line == 1 {
-            if n_undecided == 1 {
-                for &d in &FOUR_NEIGHBOURS {
-                    let pos2 = LP::of_vertex(pos) + d;
-                    if self.get_edge(pos2) == Edge::Undecided {
-                        if self.decide_edge(pos2, Edge::Line) {
-                            return true;
-                        }
-                    }
-                }
-            } else if n_undecided == 0 {
+            if n_undecided == 0 {
                 return self.set_inconsistent();
             }
+            if n_undecided == 1 {
+                Some(Edge::Line)
+            } else {
+                None
+            }
         } else if n_line == 0 && self.disallow_unused_cell {
             if n_undecided < 2 {
                 return self.set_inconsistent();
-            } else if n_undecided == 2 {
-                for &d in &FOUR_NEIGHBOURS {
-                    let pos2 = LP::of_vertex(pos) + d;
-                    if self.get_edge(pos2) == Edge::Undecided {
-                        if self.decide_edge(pos2, Edge::Line) {
-                            return true;
-                        }
-                    }
+            }
+            if n_undecided == 2 {
+                Some(Edge::Line)
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        if let Some(state) = fill {
+            for &d in FOUR_NEIGHBOURS.iter().rev() {
+                let pos2 = LP::of_vertex(pos) + d;
+                if self.get_edge(pos2) == Edge::Undecided {
+                    self.tasks.push(Task::DecideUndecided(pos2, state));
+                }
+            }
+        }
+
+        let another_end = self.another_end[pos];
+        if another_end < -1 {
+            for &d in FOUR_NEIGHBOURS.iter().rev() {
+                if self.another_end.is_valid_p(pos + d) {
+                    self.tasks.push(Task::AdjacentEnd(pos, d, another_end));
                 }
             }
         }
@@ -588,6 +666,189 @@ impl SolverField {
         */
         false
     }
+    /// Apply the rule on two clue ends next to each other: `pos`, whose chain ends at
+    /// `another_end`, and `pos + d`.
+    fn inspect_adjacent_end(&mut self, pos: P, d: D, another_end: i32) -> bool {
+        let another_end2 = self.another_end[pos + d];
+        // joining two adjacent ends of the same number right away is a
+        // canonical-form shortcut; the chain may also take a detour
+        if another_end2 < -1 && (self.canonical || another_end != another_end2) {
+            self.decide_edge(
+                LP::of_vertex(pos) + d,
+                if another_end == another_end2 {
+                    Edge::Line
+                } else {
+                    Edge::Blank
+                },
+            )
+        } else {
+            false
+        }
+    }
+    /// Find the free cells through which the line of some number has to pass, as their
+    /// removal would separate its two ends, and decide the edges around them.
+    /// A cell which two numbers have to pass through is a contradiction.
+    fn inspect_bottlenecks(&mut self) -> bool {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        let found = find_bottlenecks(self, &mut scratch);
+        if found {
+            for &(v, n) in scratch.bottlenecks.iter().rev() {
+                let pos = self.another_end.p(v);
+                let isolated = self.another_end[pos] == v as i32;
+                let n_undecided = FOUR_NEIGHBOURS
+                    .iter()
+                    .filter(|&&d| self.get_edge(LP::of_vertex(pos) + d) == Edge::Undecided)
+                    .count();
+                for &d in FOUR_NEIGHBOURS.iter().rev() {
+                    let edge = LP::of_vertex(pos) + d;
+                    if self.get_edge(edge) != Edge::Undecided {
+                        continue;
+                    }
+                    // the cell cannot be joined to the line of another number, and a cell
+                    // on no chain yet has to use its only two edges
+                    let another_end = self.another_end[pos + d];
+                    if another_end < -1 && another_end != -(n as i32 + 1) {
+                        self.tasks.push(Task::DecideUndecided(edge, Edge::Blank));
+                    } else if isolated && n_undecided == 2 {
+                        self.tasks.push(Task::DecideUndecided(edge, Edge::Line));
+                    }
+                }
+            }
+        }
+        self.scratch = scratch;
+        if !found {
+            return self.set_inconsistent();
+        }
+        self.propagate()
+    }
+}
+
+/// Collect the bottlenecks of `SolverField::inspect_bottlenecks` into `scratch.bottlenecks`,
+/// as pairs of a cell and the number which has to pass through it.
+/// Returns `false` if there is a contradiction.
+fn find_bottlenecks(field: &SolverField, scratch: &mut Scratch) -> bool {
+    let Scratch {
+        dfs,
+        required_by,
+        bottlenecks,
+        ..
+    } = scratch;
+    required_by.iter_mut().for_each(|r| *r = None);
+    bottlenecks.clear();
+
+    for n in 0..field.number_end.len() {
+        let (a, b) = field.number_end[n];
+        if a == -1 {
+            continue;
+        }
+        let (a, b) = (a as usize, b as usize);
+        if !dfs.run(field, a, b) {
+            // the ends cannot be connected at all
+            return false;
+        }
+        let mut child = b;
+        let mut v = dfs.parent[b];
+        while v != a {
+            if dfs.low[child] >= dfs.order[v] {
+                // the line passes through the whole chain if `v` is its end
+                for u in [v, field.another_end[v] as usize] {
+                    match required_by[u] {
+                        Some(m) if m != n => return false,
+                        Some(_) => (),
+                        None => {
+                            required_by[u] = Some(n);
+                            bottlenecks.push((u, n));
+                        }
+                    }
+                }
+            }
+            child = v;
+            v = dfs.parent[v];
+        }
+    }
+    true
+}
+
+/// Depth-first search over the free cells for `SolverField::inspect_bottlenecks`.
+#[derive(Clone, Default)]
+struct Dfs {
+    order: Vec<u32>,
+    low: Vec<u32>,
+    parent: Vec<usize>,
+    stack: Vec<(usize, usize)>,
+}
+
+impl Dfs {
+    /// Search from cell `src` until every cell reachable from it is visited, passing
+    /// through the free cells of `field` only, and return whether `dest` is reached.
+    /// `order` holds the (1-based) visiting order, and `low` the smallest order reachable
+    /// from the subtree of each cell through at most one back edge.
+    fn run(&mut self, field: &SolverField, src: usize, dest: usize) -> bool {
+        self.order.iter_mut().for_each(|o| *o = 0);
+        let mut n_visited = 1;
+        self.order[src] = n_visited;
+        self.low[src] = n_visited;
+        self.parent[src] = usize::MAX;
+        self.stack.push((src, 0));
+
+        while let Some(&mut (v, ref mut next)) = self.stack.last_mut() {
+            let Some(w) = Dfs::neighbour(field, v, *next, (src, dest)) else {
+                self.stack.pop();
+                if let Some(&(u, _)) = self.stack.last() {
+                    self.low[u] = self.low[u].min(self.low[v]);
+                }
+                continue;
+            };
+            *next += 1;
+            let Some(w) = w else {
+                continue;
+            };
+            if self.order[w] == 0 {
+                n_visited += 1;
+                self.order[w] = n_visited;
+                self.low[w] = n_visited;
+                self.parent[w] = v;
+                self.stack.push((w, 0));
+            } else {
+                self.low[v] = self.low[v].min(self.order[w]);
+            }
+        }
+        self.order[dest] != 0
+    }
+
+    /// The `k`-th neighbour of cell `v`, or `Some(None)` if it is not a free cell
+    /// (nor `src` or `dest`), or `None` if `k` is out of range.
+    /// The ends of a chain without a number are neighbours of each other.
+    fn neighbour(
+        field: &SolverField,
+        v: usize,
+        k: usize,
+        (src, dest): (usize, usize),
+    ) -> Option<Option<usize>> {
+        let pos = field.another_end.p(v);
+        if k < 4 {
+            let d = FOUR_NEIGHBOURS[k];
+            if field.get_edge(LP::of_vertex(pos) + d) != Edge::Undecided {
+                return Some(None);
+            }
+            let w = field.another_end.index_p(pos + d);
+            let free = !field.unused[pos + d] && field.another_end[pos + d] >= 0;
+            Some(if free || w == src || w == dest {
+                Some(w)
+            } else {
+                None
+            })
+        } else if k == 4 {
+            let another_end = field.another_end[pos];
+            Some(if another_end >= 0 && another_end as usize != v {
+                Some(another_end as usize)
+            } else {
+                None
+            })
+        } else {
+            None
+        }
+    }
 }
 impl fmt::Debug for SolverField {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -634,180 +895,1334 @@ impl fmt::Debug for SolverField {
                     _ => unreachable!(),
                 }
             }
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
 
         Ok(())
     }
 }
-struct AnswerInfo {
-    answers: Vec<LinePlacement>,
-    limit: Option<usize>,
-    terminate_on_not_fully_filled: bool,
-    found_not_fully_filled: bool,
-}
-
 pub fn solve2(
     problem: &Grid<Clue>,
     limit: Option<usize>,
     disallow_unused_cell: bool,
     terminate_on_not_fully_filled: bool,
 ) -> AnswerDetail {
-    let mut solver_field = SolverField::new(problem, disallow_unused_cell);
-    let mut answer_info = AnswerInfo {
-        answers: Vec::new(),
+    let options = SolveOptions {
         limit,
+        disallow_unused_cell,
         terminate_on_not_fully_filled,
-        found_not_fully_filled: false,
-    };
-    let mut n_steps = 0u64;
-
-    search(0, 0, &mut solver_field, &mut answer_info, &mut n_steps, 0);
-
-    let fully_checked = if let Some(limit) = limit {
-        limit == answer_info.answers.len()
-    } else {
-        true
+        ..SolveOptions::default()
     };
+    collect_answers(&mut SolutionIter::with_buffers(
+        problem,
+        &options,
+        &mut None,
+        vec![],
+        Grid::default(),
+        &mut ValidateBuffers::default(),
+    ))
+}
+pub fn solve2_with_options(problem: &Problem, options: &SolveOptions) -> AnswerDetail {
+    collect_answers(&mut SolutionIter::new(problem, options))
+}
+/// Same as `solve2_with_options`, but `callback` is called every `interval` search steps.
+pub fn solve2_with_progress(
+    problem: &Problem,
+    options: &SolveOptions,
+    interval: u64,
+    callback: impl FnMut(&Progress),
+) -> AnswerDetail {
+    collect_answers(&mut SolutionIter::new(problem, options).on_progress(interval, callback))
+}
+fn collect_answers(solutions: &mut SolutionIter) -> AnswerDetail {
+    let answers = solutions.by_ref().collect::<Vec<_>>();
+    let outcome = solutions.outcome().unwrap_or(SolveOutcome::Exhausted);
 
     AnswerDetail {
-        answers: answer_info.answers,
-        fully_checked,
-        found_not_fully_filled: answer_info.found_not_fully_filled,
-        n_steps,
+        answers,
+        outcome,
+        found_not_fully_filled: outcome == SolveOutcome::NotFullyFilled,
+        n_steps: solutions.n_steps(),
     }
 }
-fn prune_cut(field: &SolverField) -> bool {
-    let width = field.width();
-    let mut accsum = vec![0; width as usize];
+/// A solver which keeps its buffers from one problem to the next, for solving many
+/// problems in a row.
+///
+/// `solve` gives the same result as `solve2_with_options`, but once a problem at least as
+/// large has been solved, it allocates memory only for the answers it returns (and for
+/// copying `propagators` of the options).
+pub struct Solver {
+    options: SolveOptions,
+    field: Option<SolverField>,
+    stack: Vec<Frame>,
+    clues: Grid<Clue>,
+    validate: ValidateBuffers,
+}
 
-    for x in 0..width {
-        accsum[x as usize] = -field.open_end_count[x as usize];
-    }
-    for n in 0..field.number_end.len() {
-        let (a, b) = field.number_end[n];
-        if a != -1 {
-            if a < b {
-                accsum[a as usize] += 1;
-                accsum[b as usize] -= 1;
-            } else {
-                accsum[b as usize] += 1;
-                accsum[a as usize] -= 1;
-            }
+impl Solver {
+    pub fn new(options: SolveOptions) -> Solver {
+        Solver {
+            options,
+            field: None,
+            stack: vec![],
+            clues: Grid::default(),
+            validate: ValidateBuffers::default(),
         }
     }
-
-    for i in 1..(width as usize) {
-        accsum[i] += accsum[i - 1];
+    pub fn options(&self) -> &SolveOptions {
+        &self.options
     }
-    for x in 0..(width - 1) {
-        if field.undecided_count[x as usize] < accsum[x as usize] {
-            return true;
-        }
+    /// The options for the problems solved from now on.
+    pub fn options_mut(&mut self) -> &mut SolveOptions {
+        &mut self.options
     }
-    false
-}
-fn search(
-    y: i32,
-    x: i32,
-    field: &mut SolverField,
-    answer_info: &mut AnswerInfo,
-    n_steps: &mut u64,
-    line_chain: i32,
-) -> bool {
-    let mut y = y;
-    let mut x = x;
-    let mut line_chain = line_chain;
-    if x == field.width() {
-        y += 1;
-        x = 0;
-        line_chain = 0;
-    }
-    while y < field.height()
-        && field.get_edge(LP(y * 2 + 1, x * 2)) != Edge::Undecided
-        && field.get_edge(LP(y * 2, x * 2 + 1)) != Edge::Undecided
-    {
-        if x == field.width() - 1 {
-            y += 1;
-            x = 0;
-        } else {
-            x += 1;
-            if y > 0 {
-                if field.get_edge(LP(y * 2, x * 2 - 1)) == Edge::Line {
-                    if field.get_edge(LP(y * 2 - 2, x * 2 - 1)) == Edge::Line {
-                        line_chain = -field.width();
-                    } else {
-                        line_chain += 1;
-                    }
-                } else {
-                    line_chain = 0;
-                }
-            }
-            if line_chain > 0 && field.get_edge(LP(y * 2 - 1, x * 2)) == Edge::Line {
-                if field.get_edge(LP(y * 2 - 1, (x - line_chain) * 2)) == Edge::Line
-                    && field.left_clue_distance[P(y - 1, x)] >= line_chain
-                {
-                    return false;
-                }
-            }
+    pub fn solve(&mut self, problem: &Problem) -> AnswerDetail {
+        let stack = std::mem::take(&mut self.stack);
+        let clues = std::mem::take(&mut self.clues);
+        let mut solutions = SolutionIter::with_buffers(
+            problem.grid(),
+            &self.options,
+            &mut self.field,
+            stack,
+            clues,
+            &mut self.validate,
+        );
+        let ret = collect_answers(&mut solutions);
+        if solutions.field.is_some() {
+            self.field = solutions.field.take();
         }
+        self.stack = std::mem::take(&mut solutions.search.stack);
+        self.stack.clear();
+        self.clues = std::mem::take(&mut solutions.clues);
+        ret
     }
-    *n_steps += 1;
+}
 
-    if y == field.height() {
-        // answer found
-        answer_info.answers.push(field.get_line_placement());
-        if answer_info.terminate_on_not_fully_filled {
-            let mut full = true;
-            for y in 0..field.height() {
-                for x in 0..field.width() {
-                    if !field.unused[P(y, x)]
-                        && field.get_edge(LP(y * 2 - 1, x * 2)) == Edge::Blank
-                        && field.get_edge(LP(y * 2 + 1, x * 2)) == Edge::Blank
-                        && field.get_edge(LP(y * 2, x * 2 - 1)) == Edge::Blank
-                        && field.get_edge(LP(y * 2, x * 2 + 1)) == Edge::Blank
-                    {
-                        full = false;
-                    }
-                }
-            }
-            if !full {
-                answer_info.found_not_fully_filled = true;
+/// Answers of a problem, found one at a time.
+///
+/// The search is suspended after each answer and resumed on the next call to `next`,
+/// so only the current branch of the search tree is kept in memory.
+pub struct SolutionIter<'a> {
+    // `None` if the problem was rejected by `validate_problem`
+    field: Option<SolverField>,
+    search: Search<'a>,
+    limit: Option<usize>,
+    terminate_on_not_fully_filled: bool,
+    outcome: Option<SolveOutcome>,
+    // for the fingerprint of checkpoints
+    clues: Grid<Clue>,
+    disallow_unused_cell: bool,
+    canonical: bool,
+}
+
+impl<'a> SolutionIter<'a> {
+    pub fn new(problem: &Problem, options: &SolveOptions) -> SolutionIter<'a> {
+        SolutionIter::with_buffers(
+            problem.grid(),
+            options,
+            &mut None,
+            vec![],
+            Grid::default(),
+            &mut ValidateBuffers::default(),
+        )
+    }
+    /// Same as `new`, but the buffers of `field` (which is taken unless the problem is
+    /// rejected), `stack` and `clues` are reused.
+    fn with_buffers(
+        problem: &Grid<Clue>,
+        options: &SolveOptions,
+        field: &mut Option<SolverField>,
+        stack: Vec<Frame>,
+        mut clues: Grid<Clue>,
+        validate: &mut ValidateBuffers,
+    ) -> SolutionIter<'a> {
+        // `SolverField` relies on every number appearing exactly twice
+        let field = if is_valid_grid(problem, validate) {
+            Some(match field.take() {
+                Some(mut field) => {
+                    field.reset(problem, options.disallow_unused_cell, options.canonical);
+                    field
+                }
+                None => SolverField::new(problem, options.disallow_unused_cell, options.canonical),
+            })
+        } else {
+            None
+        };
+        // the initial propagation may already have found a contradiction
+        let outcome = match &field {
+            Some(field) if !field.inconsistent => None,
+            _ => Some(SolveOutcome::Exhausted),
+        };
+        let mut search = Search::new(options);
+        search.stack = stack;
+        clues.reset(problem.height(), problem.width(), NO_CLUE);
+        clues.copy_from(problem);
+        SolutionIter {
+            field,
+            search,
+            limit: options.limit,
+            terminate_on_not_fully_filled: options.terminate_on_not_fully_filled,
+            outcome,
+            clues,
+            disallow_unused_cell: options.disallow_unused_cell,
+            canonical: options.canonical,
+        }
+    }
+    /// Call `callback` every `interval` (which must be positive) search steps.
+    pub fn on_progress(
+        mut self,
+        interval: u64,
+        callback: impl FnMut(&Progress) + 'a,
+    ) -> SolutionIter<'a> {
+        assert!(interval > 0);
+        self.search.progress = Some(ProgressHook {
+            interval,
+            next_report: interval,
+            callback: Box::new(callback),
+        });
+        self
+    }
+    /// Also stop the search as soon as `flag` is set, besides `cancel` of the options.
+    pub(crate) fn stop_on(mut self, flag: Arc<AtomicBool>) -> SolutionIter<'a> {
+        self.search.budget.stop = Some(flag);
+        self
+    }
+    /// Why the search stopped, or `None` if it may still yield more answers.
+    pub fn outcome(&self) -> Option<SolveOutcome> {
+        self.outcome
+    }
+    pub fn n_steps(&self) -> u64 {
+        self.search.n_steps
+    }
+    /// FNV-1a hash of everything which determines the shape of the search tree.
+    fn fingerprint(&self) -> u64 {
+        let mut ret = 0xcbf29ce484222325u64;
+        let mut add = |v: i32| {
+            for b in v.to_le_bytes() {
+                ret ^= b as u64;
+                ret = ret.wrapping_mul(0x100000001b3);
+            }
+        };
+        add(self.clues.height());
+        add(self.clues.width());
+        add(self.disallow_unused_cell as i32);
+        add(self.canonical as i32);
+        if let Some(probing) = self.search.probing {
+            add(probing.depth);
+            add(probing.interval as i32);
+        }
+        match self.search.branching {
+            Branching::RowMajor => (),
+            Branching::MostConstrained => add(1),
+            Branching::Custom(_) => add(2),
+        }
+        if !self.search.propagators.is_empty() {
+            // negative, so as not to be confused with the values above
+            add(-(self.search.propagators.len() as i32));
+        }
+        for y in 0..self.clues.height() {
+            for x in 0..self.clues.width() {
+                add(self.clues[P(y, x)].0);
+            }
+        }
+        ret
+    }
+    /// Serialize the state of the search, so that `resume` can continue it later,
+    /// possibly in another process.
+    ///
+    /// Only the branch taken at each level of the search tree is stored; the rest of
+    /// the state is rebuilt by replaying them.
+    pub fn checkpoint(&self) -> Vec<u8> {
+        let mut ret = CHECKPOINT_MAGIC.to_vec();
+        ret.push(CHECKPOINT_VERSION);
+        ret.extend_from_slice(&self.fingerprint().to_le_bytes());
+        ret.push(if self.outcome == Some(SolveOutcome::Exhausted) {
+            CHECKPOINT_EXHAUSTED
+        } else if self.search.started {
+            CHECKPOINT_RUNNING
+        } else {
+            CHECKPOINT_NOT_STARTED
+        });
+        ret.extend_from_slice(&self.search.n_steps.to_le_bytes());
+        ret.extend_from_slice(&(self.search.n_answers as u64).to_le_bytes());
+
+        let stack = &self.search.stack;
+        ret.extend_from_slice(&(self.search.pinned as u32).to_le_bytes());
+        ret.extend_from_slice(&(stack.len() as u32).to_le_bytes());
+        if let Some((top, rest)) = stack.split_last() {
+            // the branch being explored at the top frame (if any) is finished
+            ret.push(top.branches);
+            for masks in rest.chunks(4) {
+                let mut packed = 0;
+                for (i, frame) in masks.iter().enumerate() {
+                    packed |= (frame.mask as u8) << (i * 2);
+                }
+                ret.push(packed);
+            }
+        }
+        ret
+    }
+    /// Continue the search saved by `checkpoint`.
+    ///
+    /// `problem` must be the same problem, and `options` must agree on
+    /// `disallow_unused_cell`, `canonical`, `probing`, `branching` (only whether it is
+    /// `Branching::Custom`, not the strategy itself) and the number of `propagators`,
+    /// which shape the search tree; otherwise `CheckpointError::ProblemMismatch` is
+    /// returned. The other options (`limit`, `terminate_on_not_fully_filled`, `max_steps`,
+    /// `deadline` and `cancel`) may differ.
+    pub fn resume(
+        problem: &Problem,
+        options: &SolveOptions,
+        checkpoint: &[u8],
+    ) -> Result<SolutionIter<'a>, CheckpointError> {
+        let mut ret = SolutionIter::new(problem, options);
+
+        let header_len = CHECKPOINT_MAGIC.len() + 1;
+        if checkpoint.len() < header_len + 33
+            || checkpoint[..CHECKPOINT_MAGIC.len()] != CHECKPOINT_MAGIC
+            || checkpoint[CHECKPOINT_MAGIC.len()] != CHECKPOINT_VERSION
+        {
+            return Err(CheckpointError::Malformed);
+        }
+        let read_u64 = |offset: usize| {
+            u64::from_le_bytes(checkpoint[offset..(offset + 8)].try_into().unwrap())
+        };
+        if read_u64(header_len) != ret.fingerprint() {
+            return Err(CheckpointError::ProblemMismatch);
+        }
+        let state = checkpoint[header_len + 8];
+        let n_steps = read_u64(header_len + 9);
+        let n_answers = read_u64(header_len + 17) as usize;
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes(checkpoint[offset..(offset + 4)].try_into().unwrap()) as usize
+        };
+        let pinned = read_u32(header_len + 25);
+        let depth = read_u32(header_len + 29);
+        let body = &checkpoint[(header_len + 33)..];
+        let body_len = if depth == 0 { 0 } else { 1 + (depth + 2) / 4 };
+        if body.len() != body_len || (depth > 0 && state != CHECKPOINT_RUNNING) {
+            return Err(CheckpointError::Malformed);
+        }
+
+        match state {
+            CHECKPOINT_NOT_STARTED => (),
+            CHECKPOINT_RUNNING => {
+                let Some(field) = ret.field.as_mut() else {
+                    return Err(CheckpointError::InvalidState);
+                };
+                let masks = (0..depth.saturating_sub(1))
+                    .map(|i| ((body[1 + i / 4] >> (i % 4 * 2)) & 3) as u32)
+                    .collect::<Vec<_>>();
+                if ret.outcome.is_some()
+                    || !ret
+                        .search
+                        .restore(field, &masks, body.first().copied(), pinned)
+                {
+                    return Err(CheckpointError::InvalidState);
+                }
+            }
+            CHECKPOINT_EXHAUSTED => ret.outcome = Some(SolveOutcome::Exhausted),
+            _ => return Err(CheckpointError::Malformed),
+        }
+        ret.search.n_steps = n_steps;
+        ret.search.n_answers = n_answers;
+        if ret.outcome.is_none()
+            && let Some(lim) = ret.limit
+            && n_answers >= lim
+        {
+            ret.outcome = Some(SolveOutcome::AnswerLimit);
+        }
+        Ok(ret)
+    }
+}
+
+impl<'a> SolutionIter<'a> {
+    /// Answers in the subtree of the search tree below `prefix`.
+    /// Returns `None` if `prefix` is not a path of the search tree.
+    pub fn with_prefix(
+        problem: &Problem,
+        options: &SolveOptions,
+        prefix: &SearchPrefix,
+    ) -> Option<SolutionIter<'a>> {
+        let mut ret = SolutionIter::new(problem, options);
+        let Some((&last, rest)) = prefix.0.split_last() else {
+            return Some(ret);
+        };
+        if ret.outcome.is_some() || last > 3 || rest.iter().any(|&mask| mask > 3) {
+            return None;
+        }
+        let field = ret.field.as_mut().unwrap();
+        let masks = rest.iter().map(|&mask| mask as u32).collect::<Vec<_>>();
+        if !ret
+            .search
+            .restore(field, &masks, Some(1 << last), prefix.0.len())
+        {
+            return None;
+        }
+        ret.search.n_steps = 0;
+        Some(ret)
+    }
+}
+
+/// A path from the root of the search tree of `solve2`, given by the mask chosen at each
+/// level (bit 0 for the right edge of the cell, bit 1 for the down edge; with
+/// `Branching::MostConstrained`, the index in `FOUR_NEIGHBOURS` of the edge decided, and
+/// with `Branching::Custom`, the branch given to `BranchingStrategy::decide`).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SearchPrefix(pub Vec<u8>);
+
+/// List the paths of the search tree of `solve2` down to `depth` levels, in search order.
+///
+/// Every answer lies below exactly one of them, so the answers of
+/// `SolutionIter::with_prefix` for each of them, concatenated in this order, are the
+/// answers of `solve2_with_options` (without `limit`).
+/// Paths which end up in a contradiction before `depth` are omitted.
+pub fn search_prefixes(
+    problem: &Problem,
+    options: &SolveOptions,
+    depth: usize,
+) -> Vec<SearchPrefix> {
+    let mut ret = vec![];
+    if !validate_problem(problem).is_empty() {
+        return ret;
+    }
+    let mut field = SolverField::new(
+        problem.grid(),
+        options.disallow_unused_cell,
+        options.canonical,
+    );
+    if field.inconsistent {
+        return ret;
+    }
+    let mut search = Search::new(&SolveOptions {
+        probing: options.probing,
+        branching: options.branching.clone(),
+        propagators: options.propagators.clone(),
+        ..SolveOptions::default()
+    });
+    search.split_depth = Some(depth);
+    loop {
+        match search.next_answer(&mut field) {
+            Advance::Answer | Advance::Split => ret.push(SearchPrefix(
+                search.stack.iter().map(|frame| frame.mask as u8).collect(),
+            )),
+            Advance::Exhausted => break,
+            Advance::OutOfBudget => unreachable!(),
+        }
+    }
+    ret
+}
+
+/// Solve the `shard`-th of `n_shards` parts of the search of `solve2_with_options`.
+///
+/// The parts are formed by distributing `search_prefixes(problem, options, depth)` in
+/// a round-robin fashion. Without `limit`, the answers of all parts together are exactly
+/// the answers of `solve2_with_options`. `limit` and `max_steps` apply to each part.
+pub fn solve2_shard(
+    problem: &Problem,
+    options: &SolveOptions,
+    depth: usize,
+    shard: usize,
+    n_shards: usize,
+) -> AnswerDetail {
+    assert!(shard < n_shards);
+
+    let mut answers = vec![];
+    let mut n_steps = 0;
+    let mut outcome = SolveOutcome::Exhausted;
+    let prefixes = search_prefixes(problem, options, depth);
+    for prefix in prefixes.iter().skip(shard).step_by(n_shards) {
+        let options = SolveOptions {
+            limit: options.limit.map(|limit| limit - answers.len()),
+            max_steps: options.max_steps.map(|max| max.saturating_sub(n_steps)),
+            ..options.clone()
+        };
+        let mut solutions = SolutionIter::with_prefix(problem, &options, prefix).unwrap();
+        answers.extend(solutions.by_ref());
+        n_steps += solutions.n_steps();
+        let prefix_outcome = solutions.outcome().unwrap_or(SolveOutcome::Exhausted);
+        if prefix_outcome != SolveOutcome::Exhausted {
+            outcome = prefix_outcome;
+            break;
+        }
+    }
+
+    AnswerDetail {
+        answers,
+        outcome,
+        found_not_fully_filled: outcome == SolveOutcome::NotFullyFilled,
+        n_steps,
+    }
+}
+
+const CHECKPOINT_MAGIC: [u8; 4] = *b"NLCP";
+const CHECKPOINT_VERSION: u8 = 1;
+const CHECKPOINT_NOT_STARTED: u8 = 0;
+const CHECKPOINT_RUNNING: u8 = 1;
+const CHECKPOINT_EXHAUSTED: u8 = 2;
+
+impl Iterator for SolutionIter<'_> {
+    type Item = LinePlacement;
+
+    fn next(&mut self) -> Option<LinePlacement> {
+        if self.outcome.is_some() {
+            return None;
+        }
+        let field = self.field.as_mut().unwrap();
+        match self.search.next_answer(field) {
+            Advance::Answer => (),
+            Advance::Exhausted => {
+                self.outcome = Some(SolveOutcome::Exhausted);
+                return None;
+            }
+            Advance::OutOfBudget => {
+                self.outcome = Some(SolveOutcome::Budget);
+                return None;
+            }
+            Advance::Split => unreachable!(),
+        }
+
+        if self.terminate_on_not_fully_filled && !is_fully_filled(field) {
+            self.outcome = Some(SolveOutcome::NotFullyFilled);
+        } else if let Some(lim) = self.limit
+            && self.search.n_answers >= lim
+        {
+            self.outcome = Some(SolveOutcome::AnswerLimit);
+        }
+        Some(field.get_line_placement())
+    }
+}
+/// Decide whether `problem` has exactly one solution under the plain Numberlink rules.
+///
+/// Unlike counting the answers of `solve2`, this also takes non-canonical solutions
+/// (U-turns, detours along clue-free diagonals) into account, so `Unique` is a proof
+/// and `Multiple` always carries two genuinely different solutions.
+pub fn check_uniqueness(problem: &Problem, disallow_unused_cell: bool) -> Uniqueness {
+    check_uniqueness_with(disallow_unused_cell, |options| {
+        solve2_with_options(problem, options)
+    })
+}
+fn check_uniqueness_with(
+    disallow_unused_cell: bool,
+    solve: impl Fn(&SolveOptions) -> AnswerDetail,
+) -> Uniqueness {
+    let options = SolveOptions {
+        limit: Some(2),
+        disallow_unused_cell,
+        ..SolveOptions::default()
+    };
+    // The canonical search is much cheaper and settles most puzzles on its own:
+    // two canonical answers are two distinct solutions, and if unused cells are
+    // allowed every solution can be shortened into a canonical one.
+    let mut answers = solve(&options).answers;
+    if answers.len() < 2 && (disallow_unused_cell || !answers.is_empty()) {
+        answers = solve(&SolveOptions {
+            canonical: false,
+            ..options
+        })
+        .answers;
+    }
+
+    let mut answers = answers.into_iter();
+    match (answers.next(), answers.next()) {
+        (None, _) => Uniqueness::NoSolution,
+        (Some(ans), None) => Uniqueness::Unique(ans),
+        (Some(ans1), Some(ans2)) => Uniqueness::Multiple(ans1, ans2),
+    }
+}
+/// Check whether some cut between adjacent columns or rows is crossed by fewer undecided
+/// edges than the chains which still have to cross it.
+fn prune_cut(field: &SolverField, scratch: &mut Scratch) -> bool {
+    prune_straight_cut(field, scratch, 0) || prune_straight_cut(field, scratch, 1)
+}
+/// Check the cuts between adjacent columns (`axis == 0`) or rows (`axis == 1`).
+fn prune_straight_cut(field: &SolverField, scratch: &mut Scratch, axis: usize) -> bool {
+    let open_end_count = &field.open_end_count[axis];
+    let size = open_end_count.len();
+    let coord = |id: i32| {
+        let pos = field.another_end.p(id as usize);
+        if axis == 0 { pos.x() } else { pos.y() }
+    };
+    let accsum = &mut scratch.accsum;
+    accsum.clear();
+    accsum.extend(open_end_count.iter().map(|&n| -n));
+    for n in 0..field.number_end.len() {
+        let (a, b) = field.number_end[n];
+        if a != -1 {
+            let (a, b) = (coord(a), coord(b));
+            accsum[a.min(b) as usize] += 1;
+            accsum[a.max(b) as usize] -= 1;
+        }
+    }
+
+    for i in 1..size {
+        accsum[i] += accsum[i - 1];
+    }
+    // the last entry is for the border of the grid
+    accsum[..(size - 1)]
+        .iter()
+        .zip(&field.undecided_count[axis])
+        .any(|(&n_chains, &n_undecided)| n_undecided < n_chains)
+}
+/// Check whether the two ends of some number can no longer be connected.
+/// A line between them can only pass through free cells: cells on no chain, or ends of
+/// chains without numbers, from which it follows the chain to its other end.
+fn prune_unreachable(field: &SolverField, scratch: &mut Scratch) -> bool {
+    let height = field.height();
+    let width = field.width();
+    let is_free = |pos: P| !field.unused[pos] && field.another_end[pos] >= 0;
+
+    // connected components of free cells
+    let group = &mut scratch.group;
+    group.clear();
+    group.extend(0..((height * width) as usize));
+    for y in 0..height {
+        for x in 0..width {
+            let pos = P(y, x);
+            if !is_free(pos) {
+                continue;
+            }
+            let id = field.another_end.index_p(pos);
+            let another_end = field.another_end[pos] as usize;
+            if another_end != id {
+                unite(group, id, another_end);
+            }
+            for d in [D(0, 1), D(1, 0)] {
+                if field.get_edge(LP::of_vertex(pos) + d) == Edge::Undecided && is_free(pos + d) {
+                    unite(group, id, field.another_end.index_p(pos + d));
+                }
+            }
+        }
+    }
+
+    for n in 0..field.number_end.len() {
+        let (a, b) = field.number_end[n];
+        if a == -1 {
+            continue;
+        }
+        let a = field.another_end.p(a as usize);
+        let b = field.another_end.p(b as usize);
+        let mut groups_a = [None; 4];
+        let mut reachable = false;
+        for (i, &d) in FOUR_NEIGHBOURS.iter().enumerate() {
+            if field.get_edge(LP::of_vertex(a) + d) != Edge::Undecided {
+                continue;
+            }
+            if a + d == b {
+                reachable = true;
+            } else if is_free(a + d) {
+                groups_a[i] = Some(find_root(group, field.another_end.index_p(a + d)));
+            }
+        }
+        for &d in &FOUR_NEIGHBOURS {
+            if field.get_edge(LP::of_vertex(b) + d) == Edge::Undecided && is_free(b + d) {
+                let g = find_root(group, field.another_end.index_p(b + d));
+                reachable |= groups_a.contains(&Some(g));
+            }
+        }
+        if !reachable {
+            return true;
+        }
+    }
+    false
+}
+/// Check whether some area of cells on no chain can never be covered, if every cell has
+/// to be used.
+///
+/// Lines can only enter the area from the ends of chains next to it, each of which takes
+/// one line, and every part of a line in the area enters it from one end and leaves it to
+/// another. Such a part visits the cells of the checkerboard coloring alternately, so it
+/// has one more cell of the color other than that of the ends if they have the same
+/// color, and as many cells of each color otherwise.
+fn prune_dead_region(field: &SolverField, scratch: &mut Scratch) -> bool {
+    if !field.disallow_unused_cell {
+        return false;
+    }
+    let areas = &mut scratch.areas;
+    areas.begin();
+    for y in 0..field.height() {
+        for x in 0..field.width() {
+            if areas.is_dead(field, P(y, x), usize::MAX) {
                 return true;
             }
         }
-        if let Some(lim) = answer_info.limit {
-            if answer_info.answers.len() >= lim {
-                return true;
+    }
+    false
+}
+/// Same as `prune_dead_region`, but only for the areas next to cell `pos` with at most
+/// `max_cells` cells, so that it is cheap enough to run after every branch.
+fn prune_dead_region_near(
+    field: &SolverField,
+    scratch: &mut Scratch,
+    pos: P,
+    max_cells: usize,
+) -> bool {
+    if !field.disallow_unused_cell {
+        return false;
+    }
+    let areas = &mut scratch.areas;
+    areas.begin();
+    FOUR_NEIGHBOURS
+        .iter()
+        .map(|&d| pos + d)
+        .chain(Some(pos))
+        .any(|p| field.another_end.is_valid_p(p) && areas.is_dead(field, p, max_cells))
+}
+/// Whether cell `pos` is neither unused nor on any chain.
+fn is_empty_cell(field: &SolverField, pos: P) -> bool {
+    !field.unused[pos] && field.another_end[pos] == field.another_end.index_p(pos) as i32
+}
+/// The areas of cells on no chain visited by `prune_dead_region` and its variant.
+#[derive(Clone, Default)]
+struct Areas {
+    // the area each cell belongs to; the areas are numbered from 1 on through the search,
+    // so that the grid need not be cleared for each check
+    area: Grid<i32>,
+    last_end: Grid<i32>,
+    n_areas: i32,
+    // the areas up to this one were visited by earlier checks
+    first_area: i32,
+    queue: Vec<P>,
+}
+impl Areas {
+    fn reset(&mut self, height: i32, width: i32) {
+        self.area.reset(height, width, 0);
+        self.last_end.reset(height, width, 0);
+        self.n_areas = 0;
+        self.first_area = 0;
+    }
+    /// Start a new check, in which every cell is unvisited.
+    fn begin(&mut self) {
+        let size = self.area.height() * self.area.width();
+        if self.n_areas > i32::MAX - size {
+            let (height, width) = (self.area.height(), self.area.width());
+            self.reset(height, width);
+        }
+        self.first_area = self.n_areas;
+    }
+    /// Whether `start` is in an area not visited yet which can never be covered.
+    /// Areas with more than `max_cells` cells are taken to be fine.
+    fn is_dead(&mut self, field: &SolverField, start: P, max_cells: usize) -> bool {
+        let is_empty = |pos: P| is_empty_cell(field, pos);
+        let is_end = |pos: P| {
+            let another_end = field.another_end[pos];
+            another_end < CLOSED_END
+                || (another_end >= 0 && another_end != field.another_end.index_p(pos) as i32)
+        };
+        let color = |pos: P| ((pos.y() + pos.x()) % 2) as usize;
+
+        if self.area[start] > self.first_area || !is_empty(start) {
+            return false;
+        }
+        self.n_areas += 1;
+        let id = self.n_areas;
+        self.area[start] = id;
+        self.queue.clear();
+        self.queue.push(start);
+        let mut n_cells = 0;
+        // the number of ends next to the area for each color
+        let mut n_ends = [0; 2];
+        // the number of cells of color 0, minus that of color 1
+        let mut color_diff = 0;
+        while let Some(cur) = self.queue.pop() {
+            n_cells += 1;
+            if n_cells > max_cells {
+                return false;
+            }
+            color_diff += if color(cur) == 0 { 1 } else { -1 };
+            for &d in &FOUR_NEIGHBOURS {
+                if field.get_edge(LP::of_vertex(cur) + d) != Edge::Undecided {
+                    continue;
+                }
+                let nex = cur + d;
+                if is_empty(nex) {
+                    if self.area[nex] <= self.first_area {
+                        self.area[nex] = id;
+                        self.queue.push(nex);
+                    }
+                } else if is_end(nex) && self.last_end[nex] != id {
+                    self.last_end[nex] = id;
+                    n_ends[color(nex)] += 1;
+                }
+            }
+        }
+        n_ends[0] + n_ends[1] < 2 || color_diff > n_ends[1] / 2 || -color_diff > n_ends[0] / 2
+    }
+}
+fn find_root(group: &mut [usize], mut i: usize) -> usize {
+    while group[i] != i {
+        group[i] = group[group[i]];
+        i = group[i];
+    }
+    i
+}
+fn unite(group: &mut [usize], i: usize, j: usize) {
+    let i = find_root(group, i);
+    let j = find_root(group, j);
+    group[i] = j;
+}
+fn is_fully_filled(field: &SolverField) -> bool {
+    for y in 0..field.height() {
+        for x in 0..field.width() {
+            if !field.unused[P(y, x)]
+                && field.get_edge(LP(y * 2 - 1, x * 2)) != Edge::Line
+                && field.get_edge(LP(y * 2 + 1, x * 2)) != Edge::Line
+                && field.get_edge(LP(y * 2, x * 2 - 1)) != Edge::Line
+                && field.get_edge(LP(y * 2, x * 2 + 1)) != Edge::Line
+            {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// A cell of the search tree whose right and down edges (or with
+/// `Branching::MostConstrained`, the edges around which) are being branched on.
+struct Frame {
+    y: i32,
+    x: i32,
+    line_chain: i32,
+    /// Whether the right edge had been decided to be a line before branching.
+    right_line: bool,
+    /// Masks (bit 0: right, bit 1: down, or the index of the edge in `FOUR_NEIGHBOURS`)
+    /// not tried yet, as a bit set.
+    /// They are tried in the order 3, 2, 1, 0.
+    branches: u8,
+    n_branches: u32,
+    /// Whether a checkpoint has been added for the branch currently being explored.
+    in_branch: bool,
+    /// The mask of the branch currently being explored, if `in_branch`.
+    mask: u32,
+}
+
+enum Step {
+    Pruned,
+    Answer,
+    Branch(Frame),
+}
+
+/// Depth-first search over the right and down edges of each cell in row-major order,
+/// or over the ways to extend the chains (see `Branching`).
+///
+/// The search keeps its own stack of `Frame`s instead of recursing, so it can be
+/// suspended whenever an answer is found.
+struct Search<'a> {
+    stack: Vec<Frame>,
+    started: bool,
+    n_steps: u64,
+    n_answers: usize,
+    budget: Budget,
+    progress: Option<ProgressHook<'a>>,
+    /// The number of frames at the bottom of `stack` whose other branches are left to
+    /// other shards.
+    pinned: usize,
+    /// If set, a frame is not pushed beyond this depth; `Split` is returned instead.
+    split_depth: Option<usize>,
+    probing: Option<Probing>,
+    branching: Branching,
+    propagators: Vec<Arc<dyn Propagator>>,
+}
+
+struct ProgressHook<'a> {
+    interval: u64,
+    next_report: u64,
+    callback: Box<dyn FnMut(&Progress) + 'a>,
+}
+
+enum Advance {
+    Answer,
+    Exhausted,
+    OutOfBudget,
+    /// The search reached `split_depth`.
+    Split,
+}
+
+/// Limits on a search, taken from `SolveOptions`.
+struct Budget {
+    max_steps: Option<u64>,
+    deadline: Option<Instant>,
+    cancel: Option<Arc<AtomicBool>>,
+    // set by `SolutionIter::stop_on`
+    stop: Option<Arc<AtomicBool>>,
+    n_checks: u64,
+}
+
+impl Budget {
+    // reading the clock takes longer than a search step, so it is done only occasionally
+    const CLOCK_INTERVAL: u64 = 256;
+
+    fn is_exceeded(&mut self, n_steps: u64) -> bool {
+        if let Some(max_steps) = self.max_steps
+            && n_steps >= max_steps
+        {
+            return true;
+        }
+        if [&self.cancel, &self.stop]
+            .into_iter()
+            .flatten()
+            .any(|flag| flag.load(Ordering::Relaxed))
+        {
+            return true;
+        }
+        if let Some(deadline) = self.deadline {
+            self.n_checks += 1;
+            if self.n_checks % Budget::CLOCK_INTERVAL == 1 && Instant::now() >= deadline {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl<'a> Search<'a> {
+    fn new(options: &SolveOptions) -> Search<'a> {
+        Search {
+            stack: vec![],
+            started: false,
+            n_steps: 0,
+            n_answers: 0,
+            budget: Budget {
+                max_steps: options.max_steps,
+                deadline: options.deadline,
+                cancel: options.cancel.clone(),
+                stop: None,
+                n_checks: 0,
+            },
+            progress: None,
+            pinned: 0,
+            split_depth: None,
+            probing: options.probing,
+            branching: options.branching.clone(),
+            propagators: options.propagators.clone(),
+        }
+    }
+
+    /// Advance the search until `field` holds the next answer.
+    /// After `OutOfBudget` the search can be resumed by calling this again.
+    fn next_answer(&mut self, field: &mut SolverField) -> Advance {
+        if !self.started {
+            if self.budget.is_exceeded(self.n_steps) {
+                return Advance::OutOfBudget;
+            }
+            self.started = true;
+            match self.enter_root(field) {
+                Step::Pruned => return Advance::Exhausted,
+                Step::Answer => {
+                    self.n_answers += 1;
+                    return Advance::Answer;
+                }
+                Step::Branch(_) if self.split_depth == Some(0) => return Advance::Split,
+                Step::Branch(frame) => self.stack.push(frame),
+            }
+        }
+
+        while !self.stack.is_empty() {
+            if self.budget.is_exceeded(self.n_steps) {
+                return Advance::OutOfBudget;
+            }
+            self.report_progress();
+
+            let frame = self.stack.last_mut().unwrap();
+            if frame.in_branch {
+                field.rollback();
+                frame.in_branch = false;
+            }
+            if frame.branches == 0 {
+                self.stack.pop();
+                continue;
+            }
+            let mask = 7 - frame.branches.leading_zeros();
+            frame.branches &= !(1 << mask);
+            match self.take_branch(field, mask) {
+                Step::Pruned => (),
+                Step::Answer => {
+                    self.n_answers += 1;
+                    return Advance::Answer;
+                }
+                Step::Branch(_) if self.split_depth == Some(self.stack.len()) => {
+                    return Advance::Split;
+                }
+                Step::Branch(frame) => self.stack.push(frame),
+            }
+        }
+        Advance::Exhausted
+    }
+
+    /// Decide the edges of the top frame according to `mask` and move on to the next cell.
+    fn take_branch(&mut self, field: &mut SolverField, mask: u32) -> Step {
+        let frame = self.stack.last_mut().unwrap();
+        let y = frame.y;
+        let x = frame.x;
+        let right_line = frame.right_line;
+        let line_chain = frame.line_chain;
+        field.add_checkpoint();
+        frame.mask = mask;
+        frame.in_branch = true;
+
+        let mut inconsistent;
+        let mut line_chain2 = 0;
+        // the checks below take time linear in the size of the grid (for each number in
+        // the latter), so they are done once per row (or per as many levels)
+        let check_all;
+        match &self.branching {
+            Branching::RowMajor => {
+                let right = (mask & 1) != 0;
+                let down = (mask & 2) != 0;
+                inconsistent = field.assume(
+                    LP(y * 2, x * 2 + 1),
+                    if right { Edge::Line } else { Edge::Blank },
+                );
+                if !inconsistent {
+                    inconsistent |= field.assume(
+                        LP(y * 2 + 1, x * 2),
+                        if down { Edge::Line } else { Edge::Blank },
+                    );
+                }
+                if !inconsistent {
+                    inconsistent |= field.with_scratch(prune_cut);
+                }
+                check_all = x == field.width() - 1;
+                if right || right_line {
+                    line_chain2 = if field.get_edge(LP(y * 2 - 2, x * 2 + 1)) == Edge::Line {
+                        -field.width()
+                    } else {
+                        line_chain + 1
+                    };
+                }
+            }
+            Branching::MostConstrained => {
+                inconsistent = field.assume(
+                    LP::of_vertex(P(y, x)) + FOUR_NEIGHBOURS[mask as usize],
+                    if degree(field, P(y, x)) == 1 {
+                        Edge::Line
+                    } else {
+                        Edge::Blank
+                    },
+                );
+                if !inconsistent {
+                    inconsistent |= field.with_scratch(prune_cut);
+                }
+                check_all = self.stack.len().is_multiple_of(field.width() as usize);
+            }
+            Branching::Custom(strategy) => {
+                inconsistent =
+                    strategy.decide(&mut SearchField { field }, y, x, mask) || field.inconsistent;
+                if !inconsistent {
+                    inconsistent |= field.with_scratch(prune_cut);
+                }
+                check_all = self.stack.len().is_multiple_of(field.width() as usize);
+            }
+        }
+        if !inconsistent {
+            inconsistent |= self.run_propagators(field);
+        }
+        if !inconsistent && check_all {
+            inconsistent |= field.with_scratch(|field, scratch| {
+                prune_unreachable(field, scratch) || prune_dead_region(field, scratch)
+            }) || field.inspect_bottlenecks();
+        }
+        if !inconsistent {
+            inconsistent |= field.with_scratch(|field, scratch| {
+                prune_dead_region_near(field, scratch, P(y, x), DEAD_REGION_MAX_CELLS)
+            });
+        }
+        if !inconsistent
+            && let Some(probing) = self.probing
+            && self.stack.len().is_multiple_of(probing.interval)
+        {
+            inconsistent |= field.probe(y, x, probing.depth);
+        }
+        if inconsistent {
+            return Step::Pruned;
+        }
+        self.enter(field, y, x + 1, line_chain2)
+    }
+
+    /// Run the propagators until none of them decides another edge.
+    /// Returns `true` if the field became inconsistent.
+    fn run_propagators(&self, field: &mut SolverField) -> bool {
+        if self.propagators.is_empty() {
+            return false;
+        }
+        loop {
+            let n_history = field.history.len();
+            for propagator in &self.propagators {
+                if propagator.propagate(&mut SearchField { field }) || field.inconsistent {
+                    return true;
+                }
+            }
+            if field.history.len() == n_history {
+                return false;
+            }
+        }
+    }
+
+    /// Enter the root of the search tree.
+    fn enter_root(&mut self, field: &mut SolverField) -> Step {
+        if self.run_propagators(field) {
+            return Step::Pruned;
+        }
+        self.enter(field, 0, 0, 0)
+    }
+
+    /// Rebuild the stack of a started search from the mask taken at each frame but the
+    /// top one and the branches left at the top frame (`None` if there is no frame).
+    /// The first `pinned` frames are given no other branches.
+    /// Returns `false` if they do not describe a valid state.
+    fn restore(
+        &mut self,
+        field: &mut SolverField,
+        masks: &[u32],
+        top: Option<u8>,
+        pinned: usize,
+    ) -> bool {
+        self.started = true;
+        self.pinned = pinned;
+        let Some(top) = top else {
+            return true;
+        };
+        let mut step = self.enter_root(field);
+        for &mask in masks {
+            let Step::Branch(mut frame) = step else {
+                return false;
+            };
+            if frame.branches & (1 << mask) == 0 {
+                return false;
+            }
+            if self.stack.len() < pinned {
+                frame.branches = 0;
+            } else {
+                frame.branches &= (1 << mask) - 1;
+            }
+            self.stack.push(frame);
+            step = self.take_branch(field, mask);
+        }
+        let Step::Branch(mut frame) = step else {
+            return false;
+        };
+        if top & !frame.branches != 0 {
+            return false;
+        }
+        frame.branches = top;
+        self.stack.push(frame);
+        true
+    }
+
+    fn report_progress(&mut self) {
+        let Some(progress) = &mut self.progress else {
+            return;
+        };
+        if self.n_steps < progress.next_report {
+            return;
+        }
+        progress.next_report = self.n_steps + progress.interval;
+
+        // every branch of a frame is assumed to take the same effort
+        let mut explored = 0.0;
+        let mut weight = 1.0;
+        for frame in &self.stack {
+            if frame.n_branches == 0 {
+                break;
+            }
+            let n_started = frame.n_branches - frame.branches.count_ones();
+            let n_done = if frame.in_branch {
+                n_started - 1
+            } else {
+                n_started
+            };
+            weight /= frame.n_branches as f64;
+            explored += weight * n_done as f64;
+        }
+        let top = self.stack.last().unwrap();
+        (progress.callback)(&Progress {
+            n_steps: self.n_steps,
+            y: top.y,
+            x: top.x,
+            n_answers: self.n_answers,
+            explored,
+        });
+    }
+
+    /// Move from cell `(y, x)` to the first cell whose edges are not decided yet.
+    /// With `Branching::MostConstrained` the cell with the fewest ways to go on is chosen
+    /// instead, and with `Branching::Custom` the one selected by the strategy.
+    fn enter(&mut self, field: &mut SolverField, y: i32, x: i32, line_chain: i32) -> Step {
+        if self.branching != Branching::RowMajor {
+            self.n_steps += 1;
+            let selected = match &self.branching {
+                Branching::Custom(strategy) => strategy
+                    .select(&SearchField { field })
+                    .map(|(y, x, branches)| (P(y, x), branches & 15)),
+                _ => most_constrained_cell(field),
+            };
+            return match selected {
+                Some((pos, branches)) => Step::Branch(Frame {
+                    y: pos.y(),
+                    x: pos.x(),
+                    line_chain: 0,
+                    right_line: false,
+                    branches,
+                    n_branches: branches.count_ones(),
+                    in_branch: false,
+                    mask: 0,
+                }),
+                None if is_complete(field) => Step::Answer,
+                None => Step::Pruned,
+            };
+        }
+        let mut y = y;
+        let mut x = x;
+        let mut line_chain = line_chain;
+        if x == field.width() {
+            y += 1;
+            x = 0;
+            line_chain = 0;
+        }
+        while y < field.height()
+            && field.get_edge(LP(y * 2 + 1, x * 2)) != Edge::Undecided
+            && field.get_edge(LP(y * 2, x * 2 + 1)) != Edge::Undecided
+        {
+            if x == field.width() - 1 {
+                y += 1;
+                x = 0;
+            } else {
+                x += 1;
+                if y > 0 {
+                    if field.get_edge(LP(y * 2, x * 2 - 1)) == Edge::Line {
+                        if field.get_edge(LP(y * 2 - 2, x * 2 - 1)) == Edge::Line {
+                            line_chain = -field.width();
+                        } else {
+                            line_chain += 1;
+                        }
+                    } else {
+                        line_chain = 0;
+                    }
+                }
+                if field.canonical
+                    && line_chain > 0
+                    && field.get_edge(LP(y * 2 - 1, x * 2)) == Edge::Line
+                    && field.get_edge(LP(y * 2 - 1, (x - line_chain) * 2)) == Edge::Line
+                    && field.left_clue_distance[P(y - 1, x)] >= line_chain
+                {
+                    return Step::Pruned;
+                }
+            }
+        }
+        self.n_steps += 1;
+
+        if y == field.height() {
+            return Step::Answer;
+        }
+
+        let degree_common = if field.has_clue[P(y, x)] { 1 } else { 0 }
+            + if field.get_edge(LP(y * 2, x * 2 - 1)) == Edge::Line {
+                1
+            } else {
+                0
+            }
+            + if field.get_edge(LP(y * 2, x * 2 + 1)) == Edge::Line {
+                1
+            } else {
+                0
+            }
+            + if field.get_edge(LP(y * 2 - 1, x * 2)) == Edge::Line {
+                1
+            } else {
+                0
+            }
+            + if field.get_edge(LP(y * 2 + 1, x * 2)) == Edge::Line {
+                1
+            } else {
+                0
+            };
+
+        let branches = allowed_branches(field, y, x, line_chain, degree_common);
+        Step::Branch(Frame {
+            y,
+            x,
+            line_chain,
+            right_line: field.get_edge(LP(y * 2, x * 2 + 1)) == Edge::Line,
+            branches,
+            n_branches: branches.count_ones(),
+            in_branch: false,
+            mask: 0,
+        })
+    }
+}
+
+/// The number of lines around cell `pos`, counting a clue as one.
+fn degree(field: &SolverField, pos: P) -> i32 {
+    let mut ret = if field.has_clue[pos] { 1 } else { 0 };
+    for &d in &FOUR_NEIGHBOURS {
+        if field.get_edge(LP::of_vertex(pos) + d) == Edge::Line {
+            ret += 1;
+        }
+    }
+    ret
+}
+/// The cell with the fewest ways to go on, and its undecided edges (by their index in
+/// `FOUR_NEIGHBOURS`) as a bit set. The end of a chain is extended by one of them; if
+/// every cell must be used, an unused cell with three undecided edges leaves one of them
+/// blank. Returns `None` if there is no end of a chain.
+fn most_constrained_cell(field: &SolverField) -> Option<(P, u8)> {
+    let mut ret: Option<(P, u8)> = None;
+    for y in 0..field.height() {
+        for x in 0..field.width() {
+            let pos = P(y, x);
+            if field.unused[pos] || field.another_end[pos] == CLOSED_END {
+                continue;
+            }
+            let mut branches = 0u8;
+            for (i, &d) in FOUR_NEIGHBOURS.iter().enumerate() {
+                if field.get_edge(LP::of_vertex(pos) + d) == Edge::Undecided {
+                    branches |= 1 << i;
+                }
+            }
+            let degree = degree(field, pos);
+            if degree == 0 && !(field.disallow_unused_cell && branches.count_ones() == 3) {
+                continue;
+            }
+            if degree <= 1 && ret.is_none_or(|(_, best)| branches.count_ones() < best.count_ones())
+            {
+                ret = Some((pos, branches));
             }
         }
+    }
+    ret
+}
+/// Whether the lines decided so far form an answer: the chains of all numbers are
+/// complete, there are no other chains, and every cell is used if required.
+/// The undecided edges are taken to be blank.
+fn is_complete(field: &SolverField) -> bool {
+    if field.number_end.iter().any(|&end| end != (-1, -1)) {
         return false;
     }
-
-    let degree_common = if field.has_clue[P(y, x)] { 1 } else { 0 }
-        + if field.get_edge(LP(y * 2, x * 2 - 1)) == Edge::Line {
-            1
-        } else {
-            0
-        }
-        + if field.get_edge(LP(y * 2, x * 2 + 1)) == Edge::Line {
-            1
-        } else {
-            0
-        }
-        + if field.get_edge(LP(y * 2 - 1, x * 2)) == Edge::Line {
-            1
-        } else {
-            0
+    for y in 0..field.height() {
+        for x in 0..field.width() {
+            let pos = P(y, x);
+            let another_end = field.another_end[pos];
+            if !field.unused[pos]
+                && another_end >= 0
+                && (field.disallow_unused_cell
+                    || another_end != field.another_end.index_p(pos) as i32)
+            {
+                return false;
+            }
         }
-        + if field.get_edge(LP(y * 2 + 1, x * 2)) == Edge::Line {
-            1
-        } else {
-            0
-        };
-
+    }
+    true
+}
+/// Masks (bit 0: right, bit 1: down) worth trying at cell `(y, x)`, as a bit set.
+fn allowed_branches(
+    field: &SolverField,
+    y: i32,
+    x: i32,
+    line_chain: i32,
+    degree_common: i32,
+) -> u8 {
+    let mut ret = 0;
     for mask in 0..4 {
-        let mask = 3 - mask;
         let right = (mask & 1) != 0;
         let down = (mask & 2) != 0;
 
@@ -825,53 +2240,39 @@ fn search(
 
         let right_effective = right || (field.get_edge(LP(y * 2, x * 2 + 1)) == Edge::Line);
         let down_effective = down || (field.get_edge(LP(y * 2 + 1, x * 2)) == Edge::Line);
-        if right_effective && down_effective {
-            if !field.down_right[P(y, x)] {
-                continue;
-            }
-        }
-        if right_effective && field.get_edge(LP(y * 2 - 1, x * 2 + 2)) == Edge::Line {
-            if line_chain > 0
-                && field.get_edge(LP(y * 2 - 2, x * 2 + 1)) == Edge::Blank
-                && field.get_edge(LP(y * 2 - 1, (x - line_chain) * 2)) == Edge::Line
-                && field.left_clue_distance[P(y - 1, x + 1)] >= line_chain + 1
-            {
-                continue;
-            }
-        }
-        field.add_checkpoint();
-        let mut inconsistent = false;
-
-        inconsistent |= field.decide_edge(
-            LP(y * 2, x * 2 + 1),
-            if right { Edge::Line } else { Edge::Blank },
-        );
-        if !inconsistent {
-            inconsistent |= field.decide_edge(
-                LP(y * 2 + 1, x * 2),
-                if down { Edge::Line } else { Edge::Blank },
-            );
-        }
-        if !inconsistent {
-            inconsistent |= prune_cut(field);
+        if field.canonical && right_effective && down_effective && !field.down_right[P(y, x)] {
+            continue;
         }
-        if !inconsistent {
-            let line_chain2 = if right_effective {
-                if field.get_edge(LP(y * 2 - 2, x * 2 + 1)) == Edge::Line {
-                    -field.width()
-                } else {
-                    line_chain + 1
-                }
-            } else {
-                0
-            };
-            if search(y, x + 1, field, answer_info, n_steps, line_chain2) {
-                return true;
-            }
+        if field.canonical
+            && right_effective
+            && field.get_edge(LP(y * 2 - 1, x * 2 + 2)) == Edge::Line
+            && line_chain > 0
+            && field.get_edge(LP(y * 2 - 2, x * 2 + 1)) == Edge::Blank
+            && field.get_edge(LP(y * 2 - 1, (x - line_chain) * 2)) == Edge::Line
+            && field.left_clue_distance[P(y - 1, x + 1)] > line_chain
+        {
+            continue;
         }
-        field.rollback();
+        ret |= 1 << mask;
     }
-    return false;
+    ret
+}
+
+#[cfg(test)]
+pub(crate) fn problem_from_rows(rows: &[&[i32]]) -> Problem {
+    Problem::from_clues(rows.len() as i32, rows[0].len() as i32, &rows.concat()).unwrap()
+}
+
+/// A 5x5 problem with several answers, on which the search options are tested.
+#[cfg(test)]
+pub(crate) fn small_problem() -> Problem {
+    problem_from_rows(&[
+        &[1, 0, 0, 0, 0],
+        &[0, 0, 0, 0, 0],
+        &[0, 0, 2, 0, 0],
+        &[0, 0, 0, 0, 2],
+        &[0, 0, 0, 0, 1],
+    ])
 }
 
 #[cfg(test)]
@@ -899,4 +2300,770 @@ mod tests {
         let ans = solve2(&problem, None, false, false);
         assert_eq!(ans.len(), 1);
     }
+
+    #[test]
+    fn test_solve_outcome() {
+        let problem = problem_from_rows(&[&[1, 0, 1], &[0, 0, 0]]);
+        let options = SolveOptions {
+            canonical: false,
+            ..SolveOptions::default()
+        };
+        let ans = solve2_with_options(&problem, &options);
+        assert_eq!(ans.len(), 4);
+        assert_eq!(ans.outcome, SolveOutcome::Exhausted);
+
+        let ans = solve2_with_options(
+            &problem,
+            &SolveOptions {
+                limit: Some(2),
+                ..options
+            },
+        );
+        assert_eq!(ans.len(), 2);
+        assert_eq!(ans.outcome, SolveOutcome::AnswerLimit);
+
+        let ans = solve2(problem.grid(), Some(1), false, true);
+        assert_eq!(ans.outcome, SolveOutcome::NotFullyFilled);
+
+        let problem = problem_from_rows(&[&[1, 2], &[2, 1]]);
+        let ans = solve2(problem.grid(), Some(1), false, false);
+        assert!(ans.is_empty());
+        assert_eq!(ans.outcome, SolveOutcome::Exhausted);
+    }
+
+    #[test]
+    fn test_solution_iter() {
+        let problem = problem_from_rows(&[&[1, 0, 0, 0], &[0, 0, 0, 0], &[0, 0, 0, 1]]);
+        let options = SolveOptions {
+            canonical: false,
+            ..SolveOptions::default()
+        };
+        let all = solve2_with_options(&problem, &options);
+        assert!(all.len() > 3);
+
+        let mut solutions = SolutionIter::new(&problem, &options);
+        let first = solutions.by_ref().take(3).collect::<Vec<_>>();
+        assert_eq!(first, all.answers[..3]);
+        assert_eq!(solutions.outcome(), None);
+        let rest = solutions.by_ref().collect::<Vec<_>>();
+        assert_eq!(rest, all.answers[3..]);
+        assert_eq!(solutions.outcome(), Some(SolveOutcome::Exhausted));
+        assert_eq!(solutions.n_steps(), all.n_steps);
+        assert_eq!(solutions.next(), None);
+
+        let mut solutions = SolutionIter::new(
+            &problem,
+            &SolveOptions {
+                limit: Some(2),
+                ..options.clone()
+            },
+        );
+        assert_eq!(solutions.by_ref().count(), 2);
+        assert_eq!(solutions.outcome(), Some(SolveOutcome::AnswerLimit));
+
+        let problem = problem_from_rows(&[&[1, 0, 1], &[0, 1, 0]]);
+        let mut solutions = SolutionIter::new(&problem, &options);
+        assert_eq!(solutions.next(), None);
+        assert_eq!(solutions.outcome(), Some(SolveOutcome::Exhausted));
+    }
+
+    #[test]
+    fn test_solver() {
+        let problems = [
+            problem_from_rows(&[
+                &[4, 0, 0, 1, 0, 0, 1],
+                &[0, 3, 0, 0, 2, 0, 0],
+                &[0, 0, 0, 0, 0, 0, 2],
+                &[3, 0, 0, 0, 0, 0, 0],
+                &[0, 0, 0, 0, 4, 0, 0],
+                &[0, 0, 0, 0, 0, 0, 0],
+                &[0, 0, 0, 0, 0, 0, 0],
+            ]),
+            problem_from_rows(&[&[1, 0, 0, 0], &[0, 0, 0, 0], &[0, 0, 0, 1]]),
+            problem_from_rows(&[&[1, 0, 1], &[0, 1, 0]]),
+            problem_from_rows(&[&[1, 0, 0, 0, 2], &[-1, 0, 2, 0, 1]]),
+            problem_from_rows(&[&[1], &[0], &[1]]),
+        ];
+        let mut solver = Solver::new(SolveOptions::default());
+        for (disallow_unused_cell, canonical) in [(false, true), (true, false), (false, false)] {
+            *solver.options_mut() = SolveOptions {
+                limit: Some(50),
+                disallow_unused_cell,
+                canonical,
+                ..SolveOptions::default()
+            };
+            for problem in &problems {
+                let expected = solve2_with_options(problem, solver.options());
+                let ans = solver.solve(problem);
+                assert_eq!(ans.answers, expected.answers);
+                assert_eq!(ans.outcome, expected.outcome);
+                assert_eq!(ans.n_steps, expected.n_steps);
+            }
+        }
+
+        // the buffers of a larger problem are kept
+        let capacity = solver.field.as_ref().unwrap().history.capacity();
+        assert!(capacity > 0);
+        solver.solve(&problems[1]);
+        assert_eq!(solver.field.as_ref().unwrap().history.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_solve_budget() {
+        let problem = problem_from_rows(&[&[1, 0, 0, 0], &[0, 0, 0, 0], &[0, 0, 0, 1]]);
+        let options = SolveOptions {
+            canonical: false,
+            ..SolveOptions::default()
+        };
+        let all = solve2_with_options(&problem, &options);
+        assert_eq!(all.outcome, SolveOutcome::Exhausted);
+
+        let ans = solve2_with_options(
+            &problem,
+            &SolveOptions {
+                max_steps: Some(all.n_steps / 2),
+                ..options.clone()
+            },
+        );
+        assert_eq!(ans.outcome, SolveOutcome::Budget);
+        assert_eq!(ans.n_steps, all.n_steps / 2);
+        assert!(!ans.is_empty() && ans.len() < all.len());
+        assert_eq!(ans.answers, all.answers[..ans.len()]);
+
+        let ans = solve2_with_options(
+            &problem,
+            &SolveOptions {
+                deadline: Some(Instant::now()),
+                ..options.clone()
+            },
+        );
+        assert_eq!(ans.outcome, SolveOutcome::Budget);
+        assert!(ans.is_empty());
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut solutions = SolutionIter::new(
+            &problem,
+            &SolveOptions {
+                cancel: Some(cancel.clone()),
+                ..options
+            },
+        );
+        assert!(solutions.next().is_some());
+        cancel.store(true, Ordering::Relaxed);
+        assert_eq!(solutions.next(), None);
+        assert_eq!(solutions.outcome(), Some(SolveOutcome::Budget));
+    }
+
+    #[test]
+    fn test_solve_progress() {
+        let problem = problem_from_rows(&[&[1, 0, 0, 0], &[0, 0, 0, 0], &[0, 0, 0, 1]]);
+        let options = SolveOptions {
+            canonical: false,
+            ..SolveOptions::default()
+        };
+        let mut reports = vec![];
+        let ans = solve2_with_progress(&problem, &options, 5, |progress| reports.push(*progress));
+        assert_eq!(ans.answers, solve2_with_options(&problem, &options).answers);
+
+        let n_reports = reports.len() as u64;
+        assert!(n_reports == ans.n_steps / 5 || n_reports + 1 == ans.n_steps / 5);
+        for (i, progress) in reports.iter().enumerate() {
+            assert_eq!(progress.n_steps, (i as u64 + 1) * 5);
+            assert!(0 <= progress.y && progress.y < 3 && 0 <= progress.x && progress.x < 4);
+            assert!(progress.n_answers <= ans.len());
+            assert!(0.0 <= progress.explored && progress.explored <= 1.0);
+        }
+        assert!(
+            reports
+                .windows(2)
+                .all(|w| w[0].n_answers <= w[1].n_answers && w[0].explored <= w[1].explored)
+        );
+        assert!(reports.last().unwrap().explored > 0.5);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let problem = problem_from_rows(&[&[1, 0, 0, 0], &[0, 0, 0, 0], &[0, 0, 0, 1]]);
+        let options = SolveOptions {
+            canonical: false,
+            ..SolveOptions::default()
+        };
+        let all = solve2_with_options(&problem, &options);
+
+        for n_first in 0..=all.len() {
+            let mut solutions = SolutionIter::new(&problem, &options);
+            let mut answers = solutions.by_ref().take(n_first).collect::<Vec<_>>();
+            let checkpoint = solutions.checkpoint();
+            drop(solutions);
+
+            let mut solutions = SolutionIter::resume(&problem, &options, &checkpoint).unwrap();
+            answers.extend(solutions.by_ref());
+            assert_eq!(answers, all.answers);
+            assert_eq!(solutions.n_steps(), all.n_steps);
+            assert_eq!(solutions.outcome(), Some(SolveOutcome::Exhausted));
+        }
+
+        // stopped by a budget in the middle of the search tree
+        let mut solutions = SolutionIter::new(
+            &problem,
+            &SolveOptions {
+                max_steps: Some(all.n_steps / 3),
+                ..options.clone()
+            },
+        );
+        let mut answers = solutions.by_ref().collect::<Vec<_>>();
+        assert_eq!(solutions.outcome(), Some(SolveOutcome::Budget));
+        let checkpoint = solutions.checkpoint();
+        let mut solutions = SolutionIter::resume(&problem, &options, &checkpoint).unwrap();
+        answers.extend(solutions.by_ref());
+        assert_eq!(answers, all.answers);
+
+        let other = problem_from_rows(&[&[1, 0, 0, 0], &[0, 0, 0, 0], &[0, 0, 1, 0]]);
+        assert_eq!(
+            SolutionIter::resume(&other, &options, &checkpoint).err(),
+            Some(CheckpointError::ProblemMismatch)
+        );
+        assert_eq!(
+            SolutionIter::resume(&problem, &SolveOptions::default(), &checkpoint).err(),
+            Some(CheckpointError::ProblemMismatch)
+        );
+        let probing = SolveOptions {
+            probing: Some(Probing {
+                depth: 1,
+                interval: 1,
+            }),
+            ..options.clone()
+        };
+        assert_eq!(
+            SolutionIter::resume(&problem, &probing, &checkpoint).err(),
+            Some(CheckpointError::ProblemMismatch)
+        );
+        let limited = SolveOptions {
+            limit: Some(1),
+            ..options.clone()
+        };
+        assert!(SolutionIter::resume(&problem, &limited, &checkpoint).is_ok());
+        assert_eq!(
+            SolutionIter::resume(&problem, &options, &checkpoint[..20]).err(),
+            Some(CheckpointError::Malformed)
+        );
+        let mut broken = checkpoint.clone();
+        *broken.last_mut().unwrap() ^= 0xff;
+        assert_eq!(
+            SolutionIter::resume(&problem, &options, &broken).err(),
+            Some(CheckpointError::InvalidState)
+        );
+    }
+
+    #[test]
+    fn test_solve2_shard() {
+        let problem = problem_from_rows(&[&[1, 0, 0, 0], &[0, 0, 0, 0], &[0, 0, 0, 1]]);
+        for canonical in [true, false] {
+            let options = SolveOptions {
+                canonical,
+                ..SolveOptions::default()
+            };
+            let all = solve2_with_options(&problem, &options);
+
+            for depth in 0..6 {
+                let prefixes = search_prefixes(&problem, &options, depth);
+                assert!(prefixes.iter().all(|prefix| prefix.0.len() <= depth));
+                let mut answers = vec![];
+                for prefix in &prefixes {
+                    answers.extend(SolutionIter::with_prefix(&problem, &options, prefix).unwrap());
+                }
+                assert_eq!(answers, all.answers);
+
+                for n_shards in 1..4 {
+                    let mut answers = vec![];
+                    for shard in 0..n_shards {
+                        let ans = solve2_shard(&problem, &options, depth, shard, n_shards);
+                        assert_eq!(ans.outcome, SolveOutcome::Exhausted);
+                        answers.extend(ans.answers);
+                    }
+                    assert_eq!(answers.len(), all.len());
+                    assert!(all.answers.iter().all(|ans| answers.contains(ans)));
+                }
+            }
+        }
+
+        let options = SolveOptions {
+            canonical: false,
+            ..SolveOptions::default()
+        };
+        let prefixes = search_prefixes(&problem, &options, 3);
+        assert!(prefixes.len() > 1);
+        assert_eq!(prefixes[0].0.len(), 3);
+        let bad_prefix = SearchPrefix(vec![0, 0, 0, 0, 3]);
+        assert!(SolutionIter::with_prefix(&problem, &options, &bad_prefix).is_none());
+
+        // a part can be checkpointed without leaking into the other parts
+        let mut solutions = SolutionIter::with_prefix(&problem, &options, &prefixes[0]).unwrap();
+        let expected = SolutionIter::with_prefix(&problem, &options, &prefixes[0])
+            .unwrap()
+            .collect::<Vec<_>>();
+        let mut answers = solutions.by_ref().take(1).collect::<Vec<_>>();
+        let checkpoint = solutions.checkpoint();
+        answers.extend(SolutionIter::resume(&problem, &options, &checkpoint).unwrap());
+        assert_eq!(answers, expected);
+    }
+
+    #[test]
+    fn test_large_boards() {
+        // a corridor zigzagging through a 100x100 board: every edge is forced by the
+        // initial propagation, in one long chain of deductions
+        let size = 100;
+        let mut problem = Grid::new(size, size, NO_CLUE);
+        for y in 0..size {
+            for x in 0..size {
+                if y % 2 == 1 && !(y < size - 1 && x == if y % 4 == 1 { size - 1 } else { 0 }) {
+                    problem[P(y, x)] = UNUSED;
+                }
+            }
+        }
+        problem[P(0, 0)] = Clue(1);
+        problem[P(size - 2, 0)] = Clue(1);
+        let ans = solve2(&problem, None, true, false);
+        assert_eq!(ans.len(), 1);
+        assert_eq!(ans.outcome, SolveOutcome::Exhausted);
+        assert!(ans[0].right(P(0, 0)) && ans[0].down(P(0, size - 1)));
+
+        // straight lines between the left and the right edge on each row
+        let mut problem = Grid::new(size, size, NO_CLUE);
+        for y in 0..size {
+            problem[P(y, 0)] = Clue(y + 1);
+            problem[P(y, size - 1)] = Clue(y + 1);
+        }
+        let ans = solve2(&problem, None, true, false);
+        assert_eq!(ans.len(), 1);
+        assert_eq!(ans.outcome, SolveOutcome::Exhausted);
+        assert!((0..size).all(|y| (0..(size - 1)).all(|x| ans[0].right(P(y, x)))));
+    }
+
+    #[test]
+    fn test_prune_cut() {
+        // three chains have to cross the second row through two cells
+        let problem = problem_from_rows(&[&[1, 0, 2, 0, 3], &[0, -1, -1, -1, 0], &[3, 0, 2, 0, 1]]);
+        let mut field = SolverField::new(problem.grid(), false, true);
+        assert!(!field.inconsistent);
+        assert!(!field.with_scratch(|field, scratch| prune_straight_cut(field, scratch, 0)));
+        assert!(field.with_scratch(|field, scratch| prune_straight_cut(field, scratch, 1)));
+        assert!(solve2(problem.grid(), None, false, false).is_empty());
+
+        let transposed = Symmetry {
+            transpose: true,
+            flip_y: false,
+            flip_x: false,
+        }
+        .transform_problem(&problem);
+        let mut field = SolverField::new(transposed.grid(), false, true);
+        assert!(field.with_scratch(|field, scratch| prune_straight_cut(field, scratch, 0)));
+        assert!(!field.with_scratch(|field, scratch| prune_straight_cut(field, scratch, 1)));
+    }
+
+    #[test]
+    fn test_prune_unreachable() {
+        let problem = problem_from_rows(&[
+            &[1, 0, 0, 0],
+            &[0, 0, 0, 0],
+            &[0, 0, 0, 0],
+            &[2, 0, 0, 2],
+            &[0, 0, 0, 1],
+        ]);
+        let mut field = SolverField::new(problem.grid(), false, false);
+        assert!(!field.with_scratch(prune_unreachable));
+
+        // the line of 2 separates the two cells of 1
+        for pos in [LP(5, 0), LP(4, 1), LP(4, 3), LP(4, 5), LP(5, 6)] {
+            assert!(!field.assume(pos, Edge::Line));
+        }
+        assert!(field.with_scratch(prune_unreachable));
+    }
+
+    #[test]
+    fn test_inspect_bottlenecks() {
+        // both lines have to pass through the center
+        let problem = problem_from_rows(&[&[1, 0, 2], &[-1, 0, -1], &[2, 0, 1]]);
+        let mut field = SolverField::new(problem.grid(), false, false);
+        assert!(!field.inconsistent);
+        assert!(field.inspect_bottlenecks());
+
+        let problem = problem_from_rows(&[&[1, 0, 0], &[-1, 0, -1], &[0, 0, 1]]);
+        let mut field = SolverField::new(problem.grid(), false, false);
+        assert!(!field.inspect_bottlenecks());
+        assert_eq!(field.get_edge(LP(1, 2)), Edge::Line);
+        assert_eq!(field.get_edge(LP(3, 2)), Edge::Line);
+    }
+
+    #[test]
+    fn test_prune_dead_region() {
+        let problem =
+            problem_from_rows(&[&[1, 0, 0, 0], &[0, 0, 0, 0], &[0, 0, 0, 0], &[0, 0, 1, 0]]);
+        let mut field = SolverField::new(problem.grid(), true, false);
+        assert!(!field.with_scratch(prune_dead_region));
+
+        // lines between cells of the same color have one more cell of the other color,
+        // so the cells of 1 and 2 leave some cells unused
+        let problem = problem_from_rows(&[
+            &[1, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 0, 0, 0],
+            &[0, 0, 1, 0, 0, 0],
+            &[0, 0, 0, 2, 0, 0],
+            &[0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 0, 0, 2],
+        ]);
+        let mut field = SolverField::new(problem.grid(), true, false);
+        assert!(!field.inconsistent);
+        assert!(field.with_scratch(prune_dead_region));
+        assert!(solve2(problem.grid(), None, true, false).is_empty());
+
+        // the first branch leaves an area which cannot be covered, which is found before
+        // the row is completed
+        let problem = problem_from_rows(&[
+            &[0, 0, 0, 0, 1, 2],
+            &[0, 0, 0, 0, 0, 0],
+            &[0, 0, 1, 0, 0, 0],
+            &[0, 0, 0, 0, 0, 2],
+        ]);
+        let mut field = SolverField::new(problem.grid(), true, false);
+        assert!(!field.with_scratch(prune_dead_region));
+        assert!(!field.assume(LP(0, 3), Edge::Line));
+        assert!(field.with_scratch(|field, scratch| {
+            prune_dead_region_near(field, scratch, P(0, 1), DEAD_REGION_MAX_CELLS)
+        }));
+        assert!(solve2(problem.grid(), None, true, false).is_empty());
+    }
+
+    #[test]
+    fn test_probing() {
+        let problem = problem_from_rows(&[
+            &[4, 0, 0, 1, 0, 0, 1],
+            &[0, 3, 0, 0, 2, 0, 0],
+            &[0, 0, 0, 0, 0, 0, 2],
+            &[3, 0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 0, 4, 0, 0],
+            &[0, 0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 0, 0, 0, 0],
+        ]);
+        for disallow_unused_cell in [true, false] {
+            let options = SolveOptions {
+                limit: Some(30),
+                disallow_unused_cell,
+                canonical: false,
+                ..SolveOptions::default()
+            };
+            let expected = solve2_with_options(&problem, &options);
+            for (depth, interval) in [(0, 1), (1, 1), (2, 2)] {
+                let options = SolveOptions {
+                    probing: Some(Probing { depth, interval }),
+                    ..options.clone()
+                };
+                let ans = solve2_with_options(&problem, &options);
+                assert_eq!(ans.answers, expected.answers);
+                assert!(ans.n_steps < expected.n_steps);
+
+                let mut answers = vec![];
+                for prefix in &search_prefixes(&problem, &options, 4) {
+                    answers.extend(SolutionIter::with_prefix(&problem, &options, prefix).unwrap());
+                }
+                assert_eq!(answers[..30], expected.answers[..]);
+            }
+        }
+
+        // the canonical answers may be different ones, but they exist all the same
+        let options = SolveOptions {
+            probing: Some(Probing {
+                depth: 1,
+                interval: 1,
+            }),
+            ..SolveOptions::default()
+        };
+        assert!(!solve2_with_options(&problem, &options).is_empty());
+    }
+
+    #[test]
+    fn test_most_constrained() {
+        let problem = small_problem();
+        let key = |ans: &LinePlacement| format!("{:?}", ans);
+        for disallow_unused_cell in [true, false] {
+            let options = SolveOptions {
+                disallow_unused_cell,
+                canonical: false,
+                ..SolveOptions::default()
+            };
+            let mut expected = solve2_with_options(&problem, &options).answers;
+            expected.sort_by_key(key);
+
+            let options = SolveOptions {
+                branching: Branching::MostConstrained,
+                ..options
+            };
+            let ans = solve2_with_options(&problem, &options);
+            assert_eq!(ans.outcome, SolveOutcome::Exhausted);
+            let mut answers = ans.answers.clone();
+            answers.sort_by_key(key);
+            assert_eq!(answers, expected);
+
+            let mut answers = vec![];
+            for prefix in &search_prefixes(&problem, &options, 3) {
+                answers.extend(SolutionIter::with_prefix(&problem, &options, prefix).unwrap());
+            }
+            assert_eq!(answers, ans.answers);
+
+            let mut solutions = SolutionIter::new(&problem, &options);
+            let mut answers = solutions.by_ref().take(3).collect::<Vec<_>>();
+            let checkpoint = solutions.checkpoint();
+            answers.extend(SolutionIter::resume(&problem, &options, &checkpoint).unwrap());
+            assert_eq!(answers, ans.answers);
+        }
+
+        let options = SolveOptions {
+            branching: Branching::MostConstrained,
+            terminate_on_not_fully_filled: true,
+            ..SolveOptions::default()
+        };
+        let ans = solve2_with_options(&problem, &options);
+        assert_eq!(ans.outcome, SolveOutcome::NotFullyFilled);
+        let last = &ans.answers[ans.len() - 1];
+        assert!((0..25).any(|i| last.isolated(P(i / 5, i % 5))));
+    }
+
+    #[test]
+    fn test_check_uniqueness() {
+        // the only solution is the straight line
+        let problem = problem_from_rows(&[&[1, 0, 1]]);
+        assert!(matches!(
+            check_uniqueness(&problem, false),
+            Uniqueness::Unique(_)
+        ));
+
+        // the canonical search finds the straight line only, but the detour
+        // through the bottom row is a valid solution as well
+        let problem = problem_from_rows(&[&[1, 0, 1], &[0, 0, 0]]);
+        assert_eq!(solve2(problem.grid(), None, false, false).len(), 1);
+        match check_uniqueness(&problem, false) {
+            Uniqueness::Multiple(ans1, ans2) => assert!(ans1 != ans2),
+            _ => panic!(),
+        }
+
+        let problem = problem_from_rows(&[&[1, 2], &[2, 1]]);
+        assert!(matches!(
+            check_uniqueness(&problem, false),
+            Uniqueness::NoSolution
+        ));
+    }
+
+    #[test]
+    fn test_solver_inconsistent_from_start() {
+        // the initial propagation rules out every placement; searching anyway used to
+        // report a placement without any line as an answer
+        let problem = problem_from_rows(&[&[1, 2, 1, 2]]);
+        assert_eq!(solve2(problem.grid(), None, true, false).len(), 0);
+        let problem = problem_from_rows(&[&[1, 2], &[2, 1]]);
+        assert_eq!(solve2(problem.grid(), None, true, false).len(), 0);
+
+        // none of the solutions is in canonical form when every cell must be used, so the
+        // canonical search finds nothing; the non-canonical search still finds them
+        let problem = problem_from_rows(&[
+            &[0, 0, 0, 0, 0, 0, 0, 0],
+            &[0, 1, 0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 0, 0, 0, 0, 1],
+        ]);
+        assert_eq!(solve2(problem.grid(), None, true, false).len(), 0);
+        assert!(matches!(
+            check_uniqueness(&problem, true),
+            Uniqueness::Multiple(..)
+        ));
+
+        // the only solution makes a U-turn
+        let problem = problem_from_rows(&[&[1, 1], &[0, 0], &[0, 0]]);
+        assert_eq!(solve2(problem.grid(), None, true, false).len(), 0);
+        let options = SolveOptions {
+            disallow_unused_cell: true,
+            canonical: false,
+            ..SolveOptions::default()
+        };
+        assert_eq!(solve2_with_options(&problem, &options).len(), 1);
+    }
+
+    /// Count the solutions of a small problem by trying every subset of edges.
+    fn count_by_brute_force(problem: &Grid<Clue>, disallow_unused_cell: bool) -> usize {
+        let height = problem.height();
+        let width = problem.width();
+        let n_right = (height * (width - 1)) as usize;
+        let n_edges = n_right + ((height - 1) * width) as usize;
+        let mut count = 0;
+
+        'subset: for bits in 0u64..(1u64 << n_edges) {
+            let mut placement = LinePlacement::new(height, width);
+            for i in 0..n_edges {
+                if (bits >> i) & 1 != 0 {
+                    if i < n_right {
+                        let i = i as i32;
+                        placement.set_right(P(i / (width - 1), i % (width - 1)), true);
+                    } else {
+                        let i = (i - n_right) as i32;
+                        placement.set_down(P(i / width, i % width), true);
+                    }
+                }
+            }
+
+            let degree = |pos: P| {
+                FOUR_NEIGHBOURS
+                    .iter()
+                    .filter(|&&d| placement.get_checked(LP::of_vertex(pos) + d))
+                    .count()
+            };
+            for y in 0..height {
+                for x in 0..width {
+                    let pos = P(y, x);
+                    let degree_ok = match problem[pos] {
+                        UNUSED => degree(pos) == 0,
+                        NO_CLUE => degree(pos) == 2 || (!disallow_unused_cell && degree(pos) == 0),
+                        _ => degree(pos) == 1,
+                    };
+                    if !degree_ok {
+                        continue 'subset;
+                    }
+                }
+            }
+
+            // follow each chain and make sure it ends at the same number
+            let mut n_traversed = 0;
+            for y in 0..height {
+                for x in 0..width {
+                    let pos = P(y, x);
+                    if problem[pos].0 <= 0 {
+                        continue;
+                    }
+                    let mut last = P(-1, -1);
+                    let mut cur = pos;
+                    'traverse: loop {
+                        n_traversed += 1;
+                        for &d in &FOUR_NEIGHBOURS {
+                            if cur + d != last && placement.get_checked(LP::of_vertex(cur) + d) {
+                                last = cur;
+                                cur = cur + d;
+                                continue 'traverse;
+                            }
+                        }
+                        break;
+                    }
+                    if problem[cur] != problem[pos] {
+                        continue 'subset;
+                    }
+                }
+            }
+
+            // cells not reached from any number form loops
+            let n_used = (0..height)
+                .flat_map(|y| (0..width).map(move |x| P(y, x)))
+                .filter(|&pos| degree(pos) > 0)
+                .count();
+            if n_traversed == n_used * 2 {
+                count += 1;
+            }
+        }
+
+        count
+    }
+
+    #[test]
+    fn test_solver_non_canonical() {
+        let problems: [&[&[i32]]; 5] = [
+            &[&[1, 0, 0], &[0, 0, 0], &[0, 0, 1]],
+            &[&[1, 0, 2], &[0, 0, 0], &[2, 0, 1]],
+            &[&[1, 0, 0, 0], &[0, 2, 0, 0], &[0, 0, 1, 2]],
+            &[&[1, 0, 0, 1], &[0, 0, -1, 0], &[2, 0, 0, 2]],
+            &[&[0, 0, 0, 0], &[1, 2, 0, 0], &[0, 0, 2, 1]],
+        ];
+        for rows in problems {
+            let problem = problem_from_rows(rows);
+            for disallow_unused_cell in [false, true] {
+                let options = SolveOptions {
+                    disallow_unused_cell,
+                    canonical: false,
+                    ..SolveOptions::default()
+                };
+                let ans = solve2_with_options(&problem, &options);
+                assert_eq!(
+                    ans.len(),
+                    count_by_brute_force(problem.grid(), disallow_unused_cell)
+                );
+                for i in 0..ans.len() {
+                    for j in 0..i {
+                        assert!(ans[i] != ans[j]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(test)]
+mod alloc_tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    // counts the allocations of each thread, so that tests running in parallel do not
+    // disturb each other
+    struct CountingAllocator;
+
+    thread_local! {
+        static N_ALLOCS: Cell<u64> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = N_ALLOCS.try_with(|n| n.set(n.get() + 1));
+            unsafe { System.alloc(layout) }
+        }
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let _ = N_ALLOCS.try_with(|n| n.set(n.get() + 1));
+            unsafe { System.realloc(ptr, layout, new_size) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn count_allocs<R>(f: impl FnOnce() -> R) -> (R, u64) {
+        let before = N_ALLOCS.with(|n| n.get());
+        let ret = f();
+        (ret, N_ALLOCS.with(|n| n.get()) - before)
+    }
+
+    #[test]
+    fn test_solver_allocations() {
+        let problem = problem_from_rows(&[
+            &[4, 0, 0, 1, 0, 0, 1],
+            &[0, 3, 0, 0, 2, 0, 0],
+            &[0, 0, 0, 0, 0, 0, 2],
+            &[3, 0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 0, 4, 0, 0],
+            &[0, 0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 0, 0, 0, 0],
+        ]);
+        for (disallow_unused_cell, canonical) in
+            [(false, true), (true, false), (true, true), (false, false)]
+        {
+            let options = SolveOptions {
+                limit: Some(1),
+                disallow_unused_cell,
+                canonical,
+                ..SolveOptions::default()
+            };
+            let mut solver = Solver::new(options.clone());
+            solver.solve(&problem);
+            let (ans, n_allocs) = count_allocs(|| solver.solve(&problem));
+            assert_eq!(ans.answers, solve2_with_options(&problem, &options).answers);
+            // the list of answers, and the two grids of each answer
+            assert!(n_allocs <= 3 * ans.len() as u64, "{} allocations", n_allocs);
+        }
+    }
 }
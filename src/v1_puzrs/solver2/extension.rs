@@ -0,0 +1,250 @@
+use super::*;
+
+/// The state of the search as seen by a `BranchingStrategy` or a `Propagator`.
+///
+/// The edge between cells `(y, x)` and `(y, x + 1)` is the right edge of `(y, x)`, and
+/// the one between `(y, x)` and `(y + 1, x)` is its down edge; edges outside the board
+/// are blank. The edges decided through this are undone when the search backtracks.
+pub struct SearchField<'a> {
+    pub(super) field: &'a mut SolverField,
+}
+
+impl SearchField<'_> {
+    pub fn height(&self) -> i32 {
+        self.field.height()
+    }
+    pub fn width(&self) -> i32 {
+        self.field.width()
+    }
+    /// Whether cell `(y, x)`, which must be inside the board, holds a number.
+    pub fn has_number(&self, y: i32, x: i32) -> bool {
+        self.field.has_clue[P(y, x)] && !self.field.unused[P(y, x)]
+    }
+    /// Whether cell `(y, x)`, which must be inside the board, holds `UNUSED`.
+    pub fn is_unused(&self, y: i32, x: i32) -> bool {
+        self.field.unused[P(y, x)]
+    }
+    /// Whether every cell must be used (see `SolveOptions`).
+    pub fn disallow_unused_cell(&self) -> bool {
+        self.field.disallow_unused_cell
+    }
+    pub fn right(&self, y: i32, x: i32) -> Edge {
+        self.field.get_edge(LP(y * 2, x * 2 + 1))
+    }
+    pub fn down(&self, y: i32, x: i32) -> Edge {
+        self.field.get_edge(LP(y * 2 + 1, x * 2))
+    }
+    /// Decide the right edge of cell `(y, x)` to be `state` (which must not be
+    /// `Edge::Undecided`) and propagate its consequences by the built-in rules.
+    /// Returns `true` if the field became inconsistent.
+    pub fn decide_right(&mut self, y: i32, x: i32, state: Edge) -> bool {
+        self.decide(LP(y * 2, x * 2 + 1), state)
+    }
+    /// Same as `decide_right`, but for the down edge of cell `(y, x)`.
+    pub fn decide_down(&mut self, y: i32, x: i32, state: Edge) -> bool {
+        self.decide(LP(y * 2 + 1, x * 2), state)
+    }
+    /// Report a contradiction, so that the current branch of the search is abandoned.
+    pub fn set_inconsistent(&mut self) {
+        self.field.set_inconsistent();
+    }
+    pub fn is_inconsistent(&self) -> bool {
+        self.field.inconsistent
+    }
+    fn decide(&mut self, pos: LP, state: Edge) -> bool {
+        assert!(state != Edge::Undecided);
+        self.field.inconsistent || self.field.assume(pos, state)
+    }
+}
+
+/// The order of the search given through `Branching::Custom`.
+///
+/// At each level of the search tree, `select` picks a cell and up to four branches,
+/// numbered from 0 to 3, which are then taken by `decide` from the highest one. Together
+/// the branches must cover every answer, and each of them must decide at least one edge.
+/// The branch numbers are what `SearchPrefix` and checkpoints record.
+pub trait BranchingStrategy: Send + Sync {
+    /// The cell to branch on (as reported by `Progress`) and the branches to take there
+    /// as a bit set, or `None` if nothing is left to branch on. In the latter case, the
+    /// lines decided so far are an answer if they connect every pair of numbers and
+    /// leave no other chain open, taking the undecided edges to be blank.
+    fn select(&self, field: &SearchField) -> Option<(i32, i32, u8)>;
+    /// Decide the edges of branch `branch` at cell `(y, x)`, given that the field is in
+    /// the state in which `select` chose them.
+    /// Returns `true` if the field became inconsistent.
+    fn decide(&self, field: &mut SearchField, y: i32, x: i32, branch: u32) -> bool;
+}
+
+/// An extra deduction rule, given through `SolveOptions::propagators`.
+///
+/// The propagators are run at the root of the search tree and after each branch once
+/// the built-in rules are done, over and over until none of them decides another edge.
+/// A propagator which rules out some answers adds a constraint to the puzzle, which
+/// is how variants of it can be solved.
+pub trait Propagator: Send + Sync {
+    /// Decide the edges which follow from the state of `field`, or report a
+    /// contradiction. Returns `true` if the field became inconsistent.
+    fn propagate(&self, field: &mut SearchField) -> bool;
+}
+
+impl fmt::Debug for dyn BranchingStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("BranchingStrategy")
+    }
+}
+
+impl fmt::Debug for dyn Propagator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Propagator")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut answers: Vec<LinePlacement>) -> Vec<LinePlacement> {
+        answers.sort_by_key(|ans| format!("{:?}", ans));
+        answers
+    }
+
+    /// Branches on the first undecided edge in row-major order: blank (0) or line (1).
+    struct FirstUndecided;
+
+    impl BranchingStrategy for FirstUndecided {
+        fn select(&self, field: &SearchField) -> Option<(i32, i32, u8)> {
+            for y in 0..field.height() {
+                for x in 0..field.width() {
+                    if field.right(y, x) == Edge::Undecided || field.down(y, x) == Edge::Undecided {
+                        return Some((y, x, 3));
+                    }
+                }
+            }
+            None
+        }
+        fn decide(&self, field: &mut SearchField, y: i32, x: i32, branch: u32) -> bool {
+            let state = if branch == 1 { Edge::Line } else { Edge::Blank };
+            if field.right(y, x) == Edge::Undecided {
+                field.decide_right(y, x, state)
+            } else {
+                field.decide_down(y, x, state)
+            }
+        }
+    }
+
+    /// A variant rule: lines must turn at every cell without a number.
+    struct NoStraight;
+
+    impl Propagator for NoStraight {
+        fn propagate(&self, field: &mut SearchField) -> bool {
+            for y in 0..field.height() {
+                for x in 0..field.width() {
+                    if field.has_number(y, x) {
+                        continue;
+                    }
+                    let horizontal = [field.right(y, x - 1), field.right(y, x)];
+                    let vertical = [field.down(y - 1, x), field.down(y, x)];
+                    if horizontal == [Edge::Line, Edge::Line]
+                        || vertical == [Edge::Line, Edge::Line]
+                    {
+                        field.set_inconsistent();
+                        return true;
+                    }
+                    if (horizontal[0] == Edge::Line && field.decide_right(y, x, Edge::Blank))
+                        || (horizontal[1] == Edge::Line
+                            && field.decide_right(y, x - 1, Edge::Blank))
+                        || (vertical[0] == Edge::Line && field.decide_down(y, x, Edge::Blank))
+                        || (vertical[1] == Edge::Line && field.decide_down(y - 1, x, Edge::Blank))
+                    {
+                        return true;
+                    }
+                }
+            }
+            false
+        }
+    }
+
+    fn has_straight(problem: &Grid<Clue>, ans: &LinePlacement) -> bool {
+        (0..problem.height()).any(|y| {
+            (0..problem.width()).any(|x| {
+                let pos = P(y, x);
+                problem[pos] == NO_CLUE
+                    && ((ans.right(pos + D(0, -1)) && ans.right(pos))
+                        || (ans.down(pos + D(-1, 0)) && ans.down(pos)))
+            })
+        })
+    }
+
+    #[test]
+    fn test_branching_strategy() {
+        let problem = small_problem();
+        let strategy: Arc<dyn BranchingStrategy> = Arc::new(FirstUndecided);
+        for disallow_unused_cell in [false, true] {
+            let options = SolveOptions {
+                disallow_unused_cell,
+                canonical: false,
+                ..SolveOptions::default()
+            };
+            let expected = sorted(solve2_with_options(&problem, &options).answers);
+            assert!(!expected.is_empty());
+
+            let options = SolveOptions {
+                branching: Branching::Custom(strategy.clone()),
+                ..options
+            };
+            let ans = solve2_with_options(&problem, &options);
+            assert_eq!(ans.outcome, SolveOutcome::Exhausted);
+            assert_eq!(sorted(ans.answers.clone()), expected);
+
+            let mut concatenated = vec![];
+            for prefix in search_prefixes(&problem, &options, 6) {
+                assert!(prefix.0.iter().all(|&branch| branch <= 1));
+                let solutions = SolutionIter::with_prefix(&problem, &options, &prefix).unwrap();
+                concatenated.extend(solutions);
+            }
+            assert_eq!(concatenated, ans.answers);
+        }
+    }
+
+    #[test]
+    fn test_propagator() {
+        let problem = small_problem();
+        let options = SolveOptions {
+            canonical: false,
+            ..SolveOptions::default()
+        };
+        let all = solve2_with_options(&problem, &options);
+        let expected = sorted(
+            all.answers
+                .into_iter()
+                .filter(|ans| !has_straight(problem.grid(), ans))
+                .collect(),
+        );
+        assert!(!expected.is_empty());
+
+        let propagator: Arc<dyn Propagator> = Arc::new(NoStraight);
+        for branching in [
+            Branching::RowMajor,
+            Branching::MostConstrained,
+            Branching::Custom(Arc::new(FirstUndecided)),
+        ] {
+            let options = SolveOptions {
+                propagators: vec![propagator.clone()],
+                branching,
+                ..options.clone()
+            };
+            let ans = solve2_with_options(&problem, &options);
+            assert_eq!(sorted(ans.answers), expected);
+            assert!(ans.n_steps < all.n_steps);
+        }
+
+        // the propagators are run at the root, too
+        let problem = problem_from_rows(&[&[1, 0, 1]]);
+        let options = SolveOptions {
+            disallow_unused_cell: true,
+            propagators: vec![propagator],
+            ..SolveOptions::default()
+        };
+        assert!(solve2_with_options(&problem, &options).is_empty());
+    }
+}
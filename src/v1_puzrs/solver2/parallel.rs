@@ -0,0 +1,444 @@
+use super::*;
+use std::cmp::Ordering as CmpOrdering;
+use std::sync::atomic::{AtomicU64, AtomicUsize};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+/// Work is handed over to idle threads only at these intervals (in search steps).
+const PAUSE_INTERVAL: u64 = 1024;
+
+/// A frame of the search tree whose remaining branches are to be explored by one thread.
+struct WorkItem {
+    /// The masks taken at the frames above it.
+    masks: Vec<u32>,
+    /// The branches of the frame to be explored, or `None` for the whole search tree.
+    branches: Option<u8>,
+}
+
+/// Masks taken from the root of the search tree down to an answer.
+/// Comparing them with `compare_paths` gives the order in which `solve2` finds answers.
+type Path = Vec<u32>;
+
+fn compare_paths(a: &[u32], b: &[u32]) -> CmpOrdering {
+    // masks are tried in descending order
+    for (x, y) in a.iter().zip(b.iter()) {
+        if x != y {
+            return y.cmp(x);
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+/// Whether everything below `path` comes after `cutoff` in the search order.
+fn is_beyond(path: &[u32], cutoff: &[u32]) -> bool {
+    match path.iter().zip(cutoff.iter()).find(|(x, y)| x != y) {
+        Some((x, y)) => x < y,
+        None => false,
+    }
+}
+
+struct Queue {
+    items: Vec<WorkItem>,
+    n_waiting: usize,
+    finished: bool,
+}
+
+/// Answers found so far, if the search may stop before it is exhausted.
+/// Only answers up to `cutoff` are kept, in the search order.
+struct Ranking {
+    answers: Vec<(Path, LinePlacement, bool)>,
+    cutoff: Option<Path>,
+}
+
+struct Shared<'a> {
+    root: &'a SolverField,
+    options: &'a SolveOptions,
+    n_threads: usize,
+    queue: Mutex<Queue>,
+    queue_changed: Condvar,
+    // mirrors `Queue::n_waiting` so that busy threads can read it without locking
+    n_waiting: AtomicUsize,
+    /// `Some` if `limit` or `terminate_on_not_fully_filled` is given.
+    ranking: Option<Mutex<Ranking>>,
+    n_steps: AtomicU64,
+    out_of_budget: AtomicBool,
+}
+
+impl Shared<'_> {
+    fn next_item(&self) -> Option<WorkItem> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if queue.finished {
+                return None;
+            }
+            if let Some(item) = queue.items.pop() {
+                return Some(item);
+            }
+            queue.n_waiting += 1;
+            self.n_waiting.fetch_add(1, Ordering::Relaxed);
+            if queue.n_waiting == self.n_threads {
+                // every thread is out of work
+                queue.finished = true;
+                self.queue_changed.notify_all();
+                return None;
+            }
+            queue = self.queue_changed.wait(queue).unwrap();
+            queue.n_waiting -= 1;
+            self.n_waiting.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    fn give_away(&self, item: WorkItem) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.items.push(item);
+        self.queue_changed.notify_one();
+    }
+
+    fn stop(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.finished = true;
+        self.queue_changed.notify_all();
+    }
+
+    /// Check the budget given by the options, and stop every thread if it is exceeded.
+    /// Returns the number of steps which can be taken until the next check.
+    fn check_budget(&self) -> Option<u64> {
+        let options = self.options;
+        let n_steps = self.n_steps.load(Ordering::Relaxed);
+        if self.out_of_budget.load(Ordering::Relaxed)
+            || options
+                .cancel
+                .as_ref()
+                .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+            || options
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            || options.max_steps.is_some_and(|max| n_steps >= max)
+        {
+            self.out_of_budget.store(true, Ordering::Relaxed);
+            self.stop();
+            return None;
+        }
+        Some(match options.max_steps {
+            Some(max) => (max - n_steps).min(PAUSE_INTERVAL),
+            None => PAUSE_INTERVAL,
+        })
+    }
+
+    fn cutoff(&self) -> Option<Path> {
+        self.ranking
+            .as_ref()
+            .and_then(|ranking| ranking.lock().unwrap().cutoff.clone())
+    }
+
+    /// Record an answer found at `path`, and return `false` if it is beyond the cutoff.
+    fn add_answer(&self, path: Path, answer: LinePlacement, fully_filled: bool) -> bool {
+        let Some(ranking) = &self.ranking else {
+            return true;
+        };
+        let mut ranking = ranking.lock().unwrap();
+        if let Some(cutoff) = &ranking.cutoff
+            && compare_paths(&path, cutoff) == CmpOrdering::Greater
+        {
+            return false;
+        }
+        let idx = ranking
+            .answers
+            .partition_point(|(p, _, _)| compare_paths(p, &path) == CmpOrdering::Less);
+        ranking.answers.insert(idx, (path, answer, fully_filled));
+
+        // `solve2` stops at the first answer reaching `limit` or not fully filled
+        let terminate = self.options.terminate_on_not_fully_filled;
+        let limit = self.options.limit;
+        let stop_at = ranking.answers.iter().enumerate().position(|(i, ans)| {
+            (terminate && !ans.2) || limit.is_some_and(|limit| i + 1 >= limit)
+        });
+        if let Some(stop_at) = stop_at {
+            ranking.answers.truncate(stop_at + 1);
+            ranking.cutoff = Some(ranking.answers[stop_at].0.clone());
+        }
+        true
+    }
+}
+
+fn current_path(search: &Search) -> Path {
+    search
+        .stack
+        .iter()
+        .filter(|frame| frame.in_branch)
+        .map(|frame| frame.mask)
+        .collect()
+}
+
+/// Explore work items until none is left; returns the answers found unless they are
+/// collected in `shared.ranking`.
+fn work(shared: &Shared) -> Vec<(Path, LinePlacement)> {
+    let mut answers = vec![];
+
+    while let Some(item) = shared.next_item() {
+        let mut field = shared.root.clone();
+        let mut search = Search::new(shared.options);
+        if let Some(branches) = item.branches {
+            if let Some(cutoff) = shared.cutoff() {
+                let mut first = item.masks.clone();
+                first.push(7 - branches.leading_zeros());
+                if is_beyond(&first, &cutoff) {
+                    continue;
+                }
+            }
+            let pinned = item.masks.len() + 1;
+            let restored = search.restore(&mut field, &item.masks, Some(branches), pinned);
+            assert!(restored);
+            // the frames above were counted by the thread which gave this item away
+            search.n_steps = 0;
+        }
+        let Some(n_steps_allowed) = shared.check_budget() else {
+            break;
+        };
+        search.budget.max_steps = Some(search.n_steps + n_steps_allowed);
+        let mut n_steps_reported = search.n_steps;
+
+        loop {
+            match search.next_answer(&mut field) {
+                Advance::Answer => {
+                    let path = current_path(&search);
+                    let answer = field.get_line_placement();
+                    if shared.ranking.is_some() {
+                        if !shared.add_answer(path, answer, is_fully_filled(&field)) {
+                            break;
+                        }
+                    } else {
+                        answers.push((path, answer));
+                    }
+                }
+                Advance::Exhausted => break,
+                Advance::OutOfBudget => {
+                    shared
+                        .n_steps
+                        .fetch_add(search.n_steps - n_steps_reported, Ordering::Relaxed);
+                    n_steps_reported = search.n_steps;
+                    let Some(n_steps_allowed) = shared.check_budget() else {
+                        break;
+                    };
+                    if let Some(cutoff) = shared.cutoff()
+                        && is_beyond(&current_path(&search), &cutoff)
+                    {
+                        break;
+                    }
+
+                    if shared.n_waiting.load(Ordering::Relaxed) > 0 {
+                        // hand over the topmost frame with some branches left, which is
+                        // likely to have the largest subtree
+                        let stack = &mut search.stack;
+                        if let Some(k) = stack.iter().position(|frame| frame.branches != 0) {
+                            shared.give_away(WorkItem {
+                                masks: stack[..k].iter().map(|frame| frame.mask).collect(),
+                                branches: Some(stack[k].branches),
+                            });
+                            stack[k].branches = 0;
+                        }
+                    }
+                    search.budget.max_steps = Some(search.n_steps + n_steps_allowed);
+                }
+                Advance::Split => unreachable!(),
+            }
+        }
+        shared
+            .n_steps
+            .fetch_add(search.n_steps - n_steps_reported, Ordering::Relaxed);
+    }
+
+    answers
+}
+
+/// Same as `solve2_with_options`, but the search is shared among `n_threads` threads.
+///
+/// The answers and the outcome are the same as those of `solve2_with_options` (unless the
+/// search is stopped by a budget). `n_steps` counts the steps of all threads; it may be
+/// larger if the search stops early, as other threads may have explored later parts of
+/// the search tree in the meantime. `max_steps` also counts them, and is checked less
+/// frequently.
+pub fn solve2_parallel(
+    problem: &Problem,
+    options: &SolveOptions,
+    n_threads: usize,
+) -> AnswerDetail {
+    assert!(n_threads > 0);
+
+    if !validate_problem(problem).is_empty() {
+        return AnswerDetail {
+            answers: vec![],
+            outcome: SolveOutcome::Exhausted,
+            found_not_fully_filled: false,
+            n_steps: 0,
+        };
+    }
+    let root = SolverField::new(
+        problem.grid(),
+        options.disallow_unused_cell,
+        options.canonical,
+    );
+    if root.inconsistent {
+        return AnswerDetail {
+            answers: vec![],
+            outcome: SolveOutcome::Exhausted,
+            found_not_fully_filled: false,
+            n_steps: 0,
+        };
+    }
+
+    let ranking = if options.limit.is_some() || options.terminate_on_not_fully_filled {
+        Some(Mutex::new(Ranking {
+            answers: vec![],
+            cutoff: None,
+        }))
+    } else {
+        None
+    };
+    let shared = Shared {
+        root: &root,
+        options,
+        n_threads,
+        queue: Mutex::new(Queue {
+            items: vec![WorkItem {
+                masks: vec![],
+                branches: None,
+            }],
+            n_waiting: 0,
+            finished: false,
+        }),
+        queue_changed: Condvar::new(),
+        n_waiting: AtomicUsize::new(0),
+        ranking,
+        n_steps: AtomicU64::new(0),
+        out_of_budget: AtomicBool::new(false),
+    };
+
+    let mut answers = thread::scope(|scope| {
+        let workers = (0..n_threads)
+            .map(|_| scope.spawn(|| work(&shared)))
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    let n_steps = shared.n_steps.load(Ordering::Relaxed);
+    let out_of_budget = shared.out_of_budget.load(Ordering::Relaxed);
+    let (answers, outcome) = match shared.ranking {
+        Some(ranking) => {
+            let ranking = ranking.into_inner().unwrap();
+            let outcome = if out_of_budget {
+                SolveOutcome::Budget
+            } else if ranking.answers.last().is_some_and(|ans| !ans.2)
+                && options.terminate_on_not_fully_filled
+            {
+                SolveOutcome::NotFullyFilled
+            } else if ranking.cutoff.is_some() {
+                SolveOutcome::AnswerLimit
+            } else {
+                SolveOutcome::Exhausted
+            };
+            let answers = ranking.answers.into_iter().map(|ans| ans.1).collect();
+            (answers, outcome)
+        }
+        None => {
+            answers.sort_by(|a, b| compare_paths(&a.0, &b.0));
+            let outcome = if out_of_budget {
+                SolveOutcome::Budget
+            } else {
+                SolveOutcome::Exhausted
+            };
+            (answers.into_iter().map(|ans| ans.1).collect(), outcome)
+        }
+    };
+
+    AnswerDetail {
+        answers,
+        outcome,
+        found_not_fully_filled: outcome == SolveOutcome::NotFullyFilled,
+        n_steps,
+    }
+}
+
+/// Same as `check_uniqueness`, but each search is shared among `n_threads` threads.
+pub fn check_uniqueness_parallel(
+    problem: &Problem,
+    disallow_unused_cell: bool,
+    n_threads: usize,
+) -> Uniqueness {
+    check_uniqueness_with(disallow_unused_cell, |options| {
+        solve2_parallel(problem, options, n_threads)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solve2_parallel() {
+        let problem = small_problem();
+        for canonical in [true, false] {
+            for disallow_unused_cell in [true, false] {
+                let options = SolveOptions {
+                    canonical,
+                    disallow_unused_cell,
+                    ..SolveOptions::default()
+                };
+                let expected = solve2_with_options(&problem, &options);
+                for n_threads in [1, 2, 4] {
+                    let ans = solve2_parallel(&problem, &options, n_threads);
+                    assert_eq!(ans.answers, expected.answers);
+                    assert_eq!(ans.outcome, SolveOutcome::Exhausted);
+                    assert_eq!(ans.n_steps, expected.n_steps);
+                }
+
+                for limit in [1, 5] {
+                    let options = SolveOptions {
+                        limit: Some(limit),
+                        ..options.clone()
+                    };
+                    let expected = solve2_with_options(&problem, &options);
+                    let ans = solve2_parallel(&problem, &options, 4);
+                    assert_eq!(ans.answers, expected.answers);
+                    assert_eq!(ans.outcome, expected.outcome);
+                }
+
+                let options = SolveOptions {
+                    terminate_on_not_fully_filled: true,
+                    ..options.clone()
+                };
+                let expected = solve2_with_options(&problem, &options);
+                let ans = solve2_parallel(&problem, &options, 4);
+                assert_eq!(ans.answers, expected.answers);
+                assert_eq!(ans.outcome, expected.outcome);
+            }
+        }
+
+        let options = SolveOptions {
+            canonical: false,
+            branching: Branching::MostConstrained,
+            ..SolveOptions::default()
+        };
+        let expected = solve2_with_options(&problem, &options);
+        assert_eq!(
+            solve2_parallel(&problem, &options, 4).answers,
+            expected.answers
+        );
+
+        let options = SolveOptions {
+            canonical: false,
+            max_steps: Some(0),
+            ..SolveOptions::default()
+        };
+        let ans = solve2_parallel(&problem, &options, 4);
+        assert_eq!(ans.outcome, SolveOutcome::Budget);
+
+        assert!(matches!(
+            check_uniqueness_parallel(&problem, false, 4),
+            Uniqueness::Multiple(_, _)
+        ));
+    }
+}
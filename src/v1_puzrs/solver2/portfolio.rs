@@ -0,0 +1,284 @@
+use super::*;
+use std::cell::Cell;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How often the caller's `cancel` flag is forwarded to the running searches.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// One of the 8 symmetries of a rectangular board.
+/// The board is transposed first (if `transpose`), and then flipped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Symmetry {
+    pub transpose: bool,
+    pub flip_y: bool,
+    pub flip_x: bool,
+}
+
+impl Symmetry {
+    pub const IDENTITY: Symmetry = Symmetry {
+        transpose: false,
+        flip_y: false,
+        flip_x: false,
+    };
+
+    /// Every symmetry, starting from `IDENTITY`.
+    pub fn all() -> [Symmetry; 8] {
+        let mut ret = [Symmetry::IDENTITY; 8];
+        for (i, sym) in ret.iter_mut().enumerate() {
+            sym.transpose = i & 4 != 0;
+            sym.flip_y = i & 2 != 0;
+            sym.flip_x = i & 1 != 0;
+        }
+        ret
+    }
+
+    /// The size of a `height` x `width` board after the transformation.
+    fn size(self, height: i32, width: i32) -> (i32, i32) {
+        if self.transpose {
+            (width, height)
+        } else {
+            (height, width)
+        }
+    }
+
+    /// The position of cell `pos` of a `height` x `width` board after the transformation.
+    fn apply(self, pos: P, height: i32, width: i32) -> P {
+        let P(mut y, mut x) = pos;
+        if self.transpose {
+            std::mem::swap(&mut y, &mut x);
+        }
+        let (height, width) = self.size(height, width);
+        if self.flip_y {
+            y = height - 1 - y;
+        }
+        if self.flip_x {
+            x = width - 1 - x;
+        }
+        P(y, x)
+    }
+
+    /// Transform the board of `problem`.
+    pub fn transform_problem(self, problem: &Problem) -> Problem {
+        let height = problem.height();
+        let width = problem.width();
+        let (new_height, new_width) = self.size(height, width);
+        let mut ret = Grid::new(new_height, new_width, NO_CLUE);
+        for y in 0..height {
+            for x in 0..width {
+                ret[self.apply(P(y, x), height, width)] = problem.clue(y, x);
+            }
+        }
+        Problem::from_grid(ret)
+    }
+
+    /// Map an answer of the transformed problem back to the original `height` x `width` board.
+    pub fn restore_answer(self, answer: &LinePlacement, height: i32, width: i32) -> LinePlacement {
+        let connected = |a: P, b: P| {
+            let a = self.apply(a, height, width);
+            let b = self.apply(b, height, width);
+            if a.y() == b.y() {
+                answer.right(P(a.y(), a.x().min(b.x())))
+            } else {
+                answer.down(P(a.y().min(b.y()), a.x()))
+            }
+        };
+        let mut ret = LinePlacement::new(height, width);
+        for y in 0..height {
+            for x in 0..width {
+                let pos = P(y, x);
+                if x < width - 1 && connected(pos, pos + D(0, 1)) {
+                    ret.set_right(pos, true);
+                }
+                if y < height - 1 && connected(pos, pos + D(1, 0)) {
+                    ret.set_down(pos, true);
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// Run `run` on every transformed copy of `problem` in its own thread.
+/// `run` returns its result and whether it finished; the searches are stopped through the
+/// given flag as soon as one of them finishes, or when `cancel` is set.
+/// Returns the result of the first finisher, or that of `IDENTITY` if none finished before
+/// `cancel` was set.
+fn race<T: Send>(
+    problem: &Problem,
+    cancel: Option<&AtomicBool>,
+    run: impl Fn(&Problem, &Arc<AtomicBool>) -> (T, bool) + Sync,
+) -> (Symmetry, T) {
+    let symmetries = Symmetry::all();
+    let cancelled = || cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed));
+    let stop = Arc::new(AtomicBool::new(cancelled()));
+    let (sender, receiver) = mpsc::channel();
+
+    let (winner, mut results) = thread::scope(|scope| {
+        for (i, &sym) in symmetries.iter().enumerate() {
+            let sender = sender.clone();
+            let stop = &stop;
+            let run = &run;
+            scope.spawn(move || {
+                let (result, finished) = run(&sym.transform_problem(problem), stop);
+                sender.send((i, result, finished)).unwrap();
+            });
+        }
+        drop(sender);
+
+        let mut results = (0..symmetries.len()).map(|_| None).collect::<Vec<_>>();
+        let mut winner = None;
+        loop {
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok((i, result, finished)) => {
+                    // a search which finished after `cancel` was set does not count
+                    if cancelled() {
+                        stop.store(true, Ordering::Relaxed);
+                    } else if finished && winner.is_none() {
+                        winner = Some(i);
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    results[i] = Some(result);
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if cancelled() {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        (winner.unwrap_or(0), results)
+    });
+
+    (symmetries[winner], results[winner].take().unwrap())
+}
+
+/// Same as `solve2_with_options`, but the problem is solved under all 8 board symmetries
+/// in parallel, and the answers of the first search to finish are returned.
+///
+/// The search always proceeds row by row from the top-left cell, so its running time
+/// often depends heavily on the orientation of the board. The answers are mapped back to
+/// the original board, but they may differ from those of `solve2_with_options` if `limit`
+/// is given or `canonical` is set. `max_steps` applies to each search separately, and
+/// `n_steps` is that of the winning search. If no search finishes within the budget,
+/// the answers of the search on the original board are returned.
+/// `propagators` and `Branching::Custom` are given the transformed boards.
+pub fn solve2_portfolio(problem: &Problem, options: &SolveOptions) -> AnswerDetail {
+    let height = problem.height();
+    let width = problem.width();
+    let (sym, ans) = race(problem, options.cancel.as_deref(), |problem, stop| {
+        let ans = collect_answers(&mut SolutionIter::new(problem, options).stop_on(stop.clone()));
+        let finished = ans.outcome != SolveOutcome::Budget;
+        (ans, finished)
+    });
+
+    AnswerDetail {
+        answers: ans
+            .answers
+            .iter()
+            .map(|answer| sym.restore_answer(answer, height, width))
+            .collect(),
+        ..ans
+    }
+}
+
+/// Same as `check_uniqueness`, but the problem is checked under all 8 board symmetries
+/// in parallel, and the verdict of the first check to finish is returned.
+pub fn check_uniqueness_portfolio(problem: &Problem, disallow_unused_cell: bool) -> Uniqueness {
+    let height = problem.height();
+    let width = problem.width();
+    let (sym, verdict) = race(problem, None, |problem, stop| {
+        let stopped = Cell::new(false);
+        let verdict = check_uniqueness_with(disallow_unused_cell, |options| {
+            let ans =
+                collect_answers(&mut SolutionIter::new(problem, options).stop_on(stop.clone()));
+            if ans.outcome == SolveOutcome::Budget {
+                stopped.set(true);
+            }
+            ans
+        });
+        (verdict, !stopped.get())
+    });
+
+    match verdict {
+        Uniqueness::Unique(ans) => Uniqueness::Unique(sym.restore_answer(&ans, height, width)),
+        Uniqueness::Multiple(ans1, ans2) => Uniqueness::Multiple(
+            sym.restore_answer(&ans1, height, width),
+            sym.restore_answer(&ans2, height, width),
+        ),
+        Uniqueness::NoSolution => Uniqueness::NoSolution,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symmetry() {
+        let problem = problem_from_rows(&[&[1, 0, 0, 0], &[0, 0, 2, 0], &[2, 0, 0, 1]]);
+        let options = SolveOptions {
+            canonical: false,
+            ..SolveOptions::default()
+        };
+        let mut expected = solve2_with_options(&problem, &options).answers;
+        expected.sort_by_key(|ans| format!("{:?}", ans));
+        assert!(expected.len() > 1);
+
+        for sym in Symmetry::all() {
+            let transformed = sym.transform_problem(&problem);
+            let mut answers = solve2_with_options(&transformed, &options)
+                .answers
+                .iter()
+                .map(|ans| sym.restore_answer(ans, 3, 4))
+                .collect::<Vec<_>>();
+            answers.sort_by_key(|ans| format!("{:?}", ans));
+            assert_eq!(answers, expected);
+        }
+    }
+
+    #[test]
+    fn test_solve2_portfolio() {
+        let problem = small_problem();
+        let options = SolveOptions {
+            disallow_unused_cell: true,
+            canonical: false,
+            ..SolveOptions::default()
+        };
+        let ans = solve2_portfolio(&problem, &options);
+        assert_eq!(ans.outcome, SolveOutcome::Exhausted);
+        assert_eq!(ans.len(), 24);
+        for answer in &ans.answers {
+            for pos in [P(0, 0), P(4, 4), P(2, 2), P(3, 4)] {
+                assert!(answer.is_endpoint(pos));
+            }
+            assert!((0..25).all(|i| !answer.isolated(P(i / 5, i % 5))));
+        }
+
+        let cancel = Arc::new(AtomicBool::new(true));
+        let options = SolveOptions {
+            canonical: false,
+            cancel: Some(cancel),
+            ..SolveOptions::default()
+        };
+        assert_eq!(
+            solve2_portfolio(&problem, &options).outcome,
+            SolveOutcome::Budget
+        );
+
+        assert!(matches!(
+            check_uniqueness_portfolio(&problem, false),
+            Uniqueness::Multiple(_, _)
+        ));
+        let problem = problem_from_rows(&[&[1, 0, 1], &[2, 0, 2]]);
+        match check_uniqueness_portfolio(&problem, true) {
+            Uniqueness::Unique(ans) => {
+                assert!(ans.right(P(0, 0)) && ans.right(P(0, 1)));
+                assert!(ans.right(P(1, 0)) && ans.right(P(1, 1)));
+            }
+            _ => panic!(),
+        }
+    }
+}
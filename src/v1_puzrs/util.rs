@@ -106,7 +106,7 @@ impl Mul<i32> for D {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Grid<T: Clone> {
     height: i32,
     width: i32,
@@ -115,11 +115,18 @@ pub struct Grid<T: Clone> {
 impl<T: Clone> Grid<T> {
     pub fn new(height: i32, width: i32, default: T) -> Grid<T> {
         Grid {
-            height: height,
-            width: width,
+            height,
+            width,
             data: vec![default; (height * width) as usize],
         }
     }
+    /// Resize the grid to `height` x `width` and fill it with `default`, reusing the buffer.
+    pub fn reset(&mut self, height: i32, width: i32, default: T) {
+        self.height = height;
+        self.width = width;
+        self.data.clear();
+        self.data.resize((height * width) as usize, default);
+    }
     pub fn height(&self) -> i32 {
         self.height
     }
@@ -155,6 +162,16 @@ impl<T: Clone> Grid<T> {
         LP(idx / self.width, idx % self.width)
     }
 }
+/// An empty grid, to be resized by `reset`.
+impl<T: Clone> Default for Grid<T> {
+    fn default() -> Grid<T> {
+        Grid {
+            height: 0,
+            width: 0,
+            data: vec![],
+        }
+    }
+}
 impl<T: Copy> Grid<T> {
     pub fn get_or_default_p(&self, cd: P, default: T) -> T {
         if self.is_valid_p(cd) {
@@ -166,38 +183,38 @@ impl<T: Copy> Grid<T> {
 }
 impl<T: Clone> Index<P> for Grid<T> {
     type Output = T;
-    fn index<'a>(&'a self, idx: P) -> &'a T {
+    fn index(&self, idx: P) -> &T {
         let idx = self.index_p(idx);
         &self.data[idx]
     }
 }
 impl<T: Clone> IndexMut<P> for Grid<T> {
-    fn index_mut<'a>(&'a mut self, idx: P) -> &'a mut T {
+    fn index_mut(&mut self, idx: P) -> &mut T {
         let idx = self.index_p(idx);
         &mut self.data[idx]
     }
 }
 impl<T: Clone> Index<LP> for Grid<T> {
     type Output = T;
-    fn index<'a>(&'a self, idx: LP) -> &'a T {
+    fn index(&self, idx: LP) -> &T {
         let idx = self.index_lp(idx);
         &self.data[idx]
     }
 }
 impl<T: Clone> IndexMut<LP> for Grid<T> {
-    fn index_mut<'a>(&'a mut self, idx: LP) -> &'a mut T {
+    fn index_mut(&mut self, idx: LP) -> &mut T {
         let idx = self.index_lp(idx);
         &mut self.data[idx]
     }
 }
 impl<T: Clone> Index<usize> for Grid<T> {
     type Output = T;
-    fn index<'a>(&'a self, idx: usize) -> &'a T {
+    fn index(&self, idx: usize) -> &T {
         &self.data[idx]
     }
 }
 impl<T: Clone> IndexMut<usize> for Grid<T> {
-    fn index_mut<'a>(&'a mut self, idx: usize) -> &'a mut T {
+    fn index_mut(&mut self, idx: usize) -> &mut T {
         &mut self.data[idx]
     }
 }
@@ -0,0 +1,226 @@
+use super::*;
+use super::{Grid, P};
+use std::collections::VecDeque;
+
+/// A reason why a problem cannot have any answer, found without searching.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ProblemDiagnostic {
+    /// A cell holds a negative number other than `UNUSED`.
+    InvalidClue { clue: i32, cell: (i32, i32) },
+    /// Number `clue` does not appear exactly twice; `cells` lists every occurrence.
+    ClueCount { clue: i32, cells: Vec<(i32, i32)> },
+    /// The line from `cell` cannot leave it: every neighbour is outside the grid,
+    /// `UNUSED` or a different number.
+    IsolatedClue { clue: i32, cell: (i32, i32) },
+    /// The two cells of `clue` are separated by `UNUSED` cells and other numbers.
+    UnreachablePair { clue: i32, cells: [(i32, i32); 2] },
+}
+
+/// Check the clue placement of `problem` before solving it.
+/// An empty result does not imply that the problem has an answer, but a non-empty one
+/// implies that it has none. Cells are given as `(y, x)`.
+pub fn validate_problem(problem: &Problem) -> Vec<ProblemDiagnostic> {
+    validate_grid(problem.grid())
+}
+
+/// Same as `validate_problem`, for the clue grid itself.
+pub(crate) fn validate_grid(problem: &Grid<Clue>) -> Vec<ProblemDiagnostic> {
+    let mut ret = vec![];
+    check_grid(problem, &mut ValidateBuffers::default(), |diagnostic| {
+        ret.push(diagnostic);
+        true
+    });
+    ret
+}
+
+/// Whether `validate_grid` finds nothing on `problem`.
+/// Once `buffers` are large enough, a valid problem is checked without allocating.
+pub(crate) fn is_valid_grid(problem: &Grid<Clue>, buffers: &mut ValidateBuffers) -> bool {
+    let mut valid = true;
+    check_grid(problem, buffers, |_| {
+        valid = false;
+        false
+    });
+    valid
+}
+
+/// Buffers reused across the runs of `is_valid_grid`.
+#[derive(Clone, Default)]
+pub(crate) struct ValidateBuffers {
+    // the cells of the numbers, sorted by the number
+    numbers: Vec<(i32, P)>,
+    visited: Grid<bool>,
+    queue: VecDeque<P>,
+}
+
+/// Pass each diagnostic of `problem` to `report`, which returns whether to go on.
+fn check_grid(
+    problem: &Grid<Clue>,
+    buffers: &mut ValidateBuffers,
+    mut report: impl FnMut(ProblemDiagnostic) -> bool,
+) {
+    let height = problem.height();
+    let width = problem.width();
+
+    let numbers = &mut buffers.numbers;
+    numbers.clear();
+    for y in 0..height {
+        for x in 0..width {
+            let pos = P(y, x);
+            let Clue(c) = problem[pos];
+            if c < -1 {
+                if !report(ProblemDiagnostic::InvalidClue {
+                    clue: c,
+                    cell: (y, x),
+                }) {
+                    return;
+                }
+            } else if c > 0 {
+                numbers.push((c, pos));
+            }
+        }
+    }
+    numbers.sort_unstable_by_key(|&(c, pos)| (c, pos.y(), pos.x()));
+
+    let mut i = 0;
+    while i < buffers.numbers.len() {
+        let clue = buffers.numbers[i].0;
+        let n = buffers.numbers[i..]
+            .iter()
+            .take_while(|&&(c, _)| c == clue)
+            .count();
+        let cells = &buffers.numbers[i..(i + n)];
+        i += n;
+        if n != 2 {
+            if !report(ProblemDiagnostic::ClueCount {
+                clue,
+                cells: cells.iter().map(|(_, pos)| (pos.y(), pos.x())).collect(),
+            }) {
+                return;
+            }
+            continue;
+        }
+        let (a, b) = (cells[0].1, cells[1].1);
+
+        let mut isolated = false;
+        for cell in [a, b] {
+            let can_leave = FOUR_NEIGHBOURS.iter().any(|&d| {
+                let c2 = problem.get_or_default_p(cell + d, UNUSED);
+                c2 == NO_CLUE || c2 == Clue(clue)
+            });
+            if !can_leave {
+                if !report(ProblemDiagnostic::IsolatedClue {
+                    clue,
+                    cell: (cell.y(), cell.x()),
+                }) {
+                    return;
+                }
+                isolated = true;
+            }
+        }
+        if !isolated
+            && !is_reachable(problem, a, b, &mut buffers.visited, &mut buffers.queue)
+            && !report(ProblemDiagnostic::UnreachablePair {
+                clue,
+                cells: [(a.y(), a.x()), (b.y(), b.x())],
+            })
+        {
+            return;
+        }
+    }
+}
+
+/// Whether `dest` can be reached from `src` only through cells without clues.
+fn is_reachable(
+    problem: &Grid<Clue>,
+    src: P,
+    dest: P,
+    visited: &mut Grid<bool>,
+    q: &mut VecDeque<P>,
+) -> bool {
+    visited.reset(problem.height(), problem.width(), false);
+    q.clear();
+    visited[src] = true;
+    q.push_back(src);
+
+    while let Some(cur) = q.pop_front() {
+        for &d in &FOUR_NEIGHBOURS {
+            let nex = cur + d;
+            if nex == dest {
+                return true;
+            }
+            if problem.get_or_default_p(nex, UNUSED) == NO_CLUE && !visited[nex] {
+                visited[nex] = true;
+                q.push_back(nex);
+            }
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_problem() {
+        let problem = problem_from_rows(&[&[1, 0, 2], &[0, 0, 0], &[2, 0, 1]]);
+        assert_eq!(validate_problem(&problem), vec![]);
+
+        let problem = problem_from_rows(&[&[1, 0, 2], &[0, 1, 0], &[3, 0, 1]]);
+        assert_eq!(
+            validate_problem(&problem),
+            vec![
+                ProblemDiagnostic::ClueCount {
+                    clue: 1,
+                    cells: vec![(0, 0), (1, 1), (2, 2)],
+                },
+                ProblemDiagnostic::ClueCount {
+                    clue: 2,
+                    cells: vec![(0, 2)],
+                },
+                ProblemDiagnostic::ClueCount {
+                    clue: 3,
+                    cells: vec![(2, 0)],
+                },
+            ]
+        );
+
+        let problem = problem_from_rows(&[
+            &[-1, -1, -1, 0],
+            &[1, 2, -1, 1],
+            &[-1, -1, 0, 2],
+            &[0, -2, 0, 0],
+        ]);
+        assert_eq!(
+            validate_problem(&problem),
+            vec![
+                ProblemDiagnostic::InvalidClue {
+                    clue: -2,
+                    cell: (3, 1),
+                },
+                ProblemDiagnostic::IsolatedClue {
+                    clue: 1,
+                    cell: (1, 0),
+                },
+                ProblemDiagnostic::IsolatedClue {
+                    clue: 2,
+                    cell: (1, 1),
+                },
+            ]
+        );
+
+        let problem = problem_from_rows(&[&[1, 0, -1, 0], &[0, 2, -1, 1], &[2, 0, -1, 0]]);
+        assert_eq!(
+            validate_problem(&problem),
+            vec![ProblemDiagnostic::UnreachablePair {
+                clue: 1,
+                cells: [(0, 0), (1, 3)],
+            }]
+        );
+
+        let problem = problem_from_rows(&[&[i32::MAX, 0, i32::MAX]]);
+        assert_eq!(validate_problem(&problem), vec![]);
+    }
+}